@@ -18,6 +18,8 @@ use ruma::{
 pub async fn create_alias_route(
     body: Ruma<create_alias::v3::Request>,
 ) -> Result<create_alias::v3::Response> {
+    services().globals.ensure_writable()?;
+
     if body.room_alias.server_name() != services().globals.server_name() {
         return Err(Error::BadRequest(
             ErrorKind::InvalidParam,
@@ -34,6 +36,19 @@ pub async fn create_alias_route(
         return Err(Error::Conflict("Alias already exists."));
     }
 
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+
+    if !services().users.is_admin(sender_user)?
+        && services().rooms.alias.alias_limit_reached(&body.room_id)?
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::LimitExceeded {
+                retry_after_ms: None,
+            },
+            "Room has too many aliases.",
+        ));
+    }
+
     services()
         .rooms
         .alias
@@ -51,6 +66,8 @@ pub async fn create_alias_route(
 pub async fn delete_alias_route(
     body: Ruma<delete_alias::v3::Request>,
 ) -> Result<delete_alias::v3::Response> {
+    services().globals.ensure_writable()?;
+
     if body.room_alias.server_name() != services().globals.server_name() {
         return Err(Error::BadRequest(
             ErrorKind::InvalidParam,
@@ -16,6 +16,8 @@ use ruma::api::client::{
 pub async fn create_backup_version_route(
     body: Ruma<create_backup_version::v3::Request>,
 ) -> Result<create_backup_version::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     let version = services()
         .key_backups
@@ -30,6 +32,8 @@ pub async fn create_backup_version_route(
 pub async fn update_backup_version_route(
     body: Ruma<update_backup_version::v3::Request>,
 ) -> Result<update_backup_version::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     services()
         .key_backups
@@ -98,6 +102,8 @@ pub async fn get_backup_info_route(
 pub async fn delete_backup_version_route(
     body: Ruma<delete_backup_version::v3::Request>,
 ) -> Result<delete_backup_version::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     services()
@@ -117,6 +123,8 @@ pub async fn delete_backup_version_route(
 pub async fn add_backup_keys_route(
     body: Ruma<add_backup_keys::v3::Request>,
 ) -> Result<add_backup_keys::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if Some(&body.version)
@@ -164,6 +172,8 @@ pub async fn add_backup_keys_route(
 pub async fn add_backup_keys_for_room_route(
     body: Ruma<add_backup_keys_for_room::v3::Request>,
 ) -> Result<add_backup_keys_for_room::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if Some(&body.version)
@@ -209,6 +219,8 @@ pub async fn add_backup_keys_for_room_route(
 pub async fn add_backup_keys_for_session_route(
     body: Ruma<add_backup_keys_for_session::v3::Request>,
 ) -> Result<add_backup_keys_for_session::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if Some(&body.version)
@@ -295,6 +307,8 @@ pub async fn get_backup_keys_for_session_route(
 pub async fn delete_backup_keys_route(
     body: Ruma<delete_backup_keys::v3::Request>,
 ) -> Result<delete_backup_keys::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     services()
@@ -318,6 +332,8 @@ pub async fn delete_backup_keys_route(
 pub async fn delete_backup_keys_for_room_route(
     body: Ruma<delete_backup_keys_for_room::v3::Request>,
 ) -> Result<delete_backup_keys_for_room::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     services()
@@ -341,6 +357,8 @@ pub async fn delete_backup_keys_for_room_route(
 pub async fn delete_backup_keys_for_session_route(
     body: Ruma<delete_backup_keys_for_session::v3::Request>,
 ) -> Result<delete_backup_keys_for_session::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     services().key_backups.delete_room_key(
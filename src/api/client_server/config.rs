@@ -19,6 +19,8 @@ use serde_json::{json, value::RawValue as RawJsonValue};
 pub async fn set_global_account_data_route(
     body: Ruma<set_global_account_data::v3::Request>,
 ) -> Result<set_global_account_data::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let data: serde_json::Value = serde_json::from_str(body.data.json().get())
@@ -45,6 +47,8 @@ pub async fn set_global_account_data_route(
 pub async fn set_room_account_data_route(
     body: Ruma<set_room_account_data::v3::Request>,
 ) -> Result<set_room_account_data::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let data: serde_json::Value = serde_json::from_str(body.data.json().get())
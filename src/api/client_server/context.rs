@@ -69,6 +69,12 @@ pub async fn get_context_route(
         lazy_loaded.insert(base_event.sender.as_str().to_owned());
     }
 
+    let mut base_event = (*base_event).clone();
+    services().rooms.timeline.enrich_unsigned(
+        &mut base_event,
+        sender_user,
+        body.sender_device.as_deref(),
+    )?;
     let base_event = base_event.to_room_event();
 
     let events_before: Vec<_> = services()
@@ -107,8 +113,15 @@ pub async fn get_context_route(
 
     let events_before: Vec<_> = events_before
         .into_iter()
-        .map(|(_, pdu)| pdu.to_room_event())
-        .collect();
+        .map(|(_, mut pdu)| {
+            services().rooms.timeline.enrich_unsigned(
+                &mut pdu,
+                sender_user,
+                Some(sender_device),
+            )?;
+            Ok(pdu.to_room_event())
+        })
+        .collect::<Result<_>>()?;
 
     let events_after: Vec<_> = services()
         .rooms
@@ -165,8 +178,15 @@ pub async fn get_context_route(
 
     let events_after: Vec<_> = events_after
         .into_iter()
-        .map(|(_, pdu)| pdu.to_room_event())
-        .collect();
+        .map(|(_, mut pdu)| {
+            services().rooms.timeline.enrich_unsigned(
+                &mut pdu,
+                sender_user,
+                Some(sender_device),
+            )?;
+            Ok(pdu.to_room_event())
+        })
+        .collect::<Result<_>>()?;
 
     let mut state = Vec::new();
 
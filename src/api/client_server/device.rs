@@ -46,6 +46,8 @@ pub async fn get_device_route(
 pub async fn update_device_route(
     body: Ruma<update_device::v3::Request>,
 ) -> Result<update_device::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let mut device = services()
@@ -74,6 +76,8 @@ pub async fn update_device_route(
 pub async fn delete_device_route(
     body: Ruma<delete_device::v3::Request>,
 ) -> Result<delete_device::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
@@ -128,6 +132,8 @@ pub async fn delete_device_route(
 pub async fn delete_devices_route(
     body: Ruma<delete_devices::v3::Request>,
 ) -> Result<delete_devices::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
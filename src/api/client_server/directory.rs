@@ -43,6 +43,7 @@ pub async fn get_public_rooms_filtered_route(
         body.since.as_deref(),
         &body.filter,
         &body.room_network,
+        false,
     )
     .await
 }
@@ -61,6 +62,7 @@ pub async fn get_public_rooms_route(
         body.since.as_deref(),
         &Filter::default(),
         &RoomNetwork::Matrix,
+        false,
     )
     .await?;
 
@@ -80,6 +82,8 @@ pub async fn get_public_rooms_route(
 pub async fn set_room_visibility_route(
     body: Ruma<set_room_visibility::v3::Request>,
 ) -> Result<set_room_visibility::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if !services().rooms.metadata.exists(&body.room_id)? {
@@ -129,7 +133,8 @@ pub(crate) async fn get_public_rooms_filtered_helper(
     limit: Option<UInt>,
     since: Option<&str>,
     filter: &Filter,
-    _network: &RoomNetwork,
+    network: &RoomNetwork,
+    for_federation: bool,
 ) -> Result<get_public_rooms_filtered::v3::Response> {
     if let Some(other_server) =
         server.filter(|server| *server != services().globals.server_name().as_str())
@@ -158,6 +163,16 @@ pub(crate) async fn get_public_rooms_filtered_helper(
         });
     }
 
+    if let RoomNetwork::ThirdParty(_) = network {
+        // We don't bridge any third party networks
+        return Ok(get_public_rooms_filtered::v3::Response {
+            chunk: Vec::new(),
+            prev_batch: None,
+            next_batch: None,
+            total_room_count_estimate: Some(0_u32.into()),
+        });
+    }
+
     let limit = limit.map_or(10, u64::from);
     let mut num_since = 0_u64;
 
@@ -188,6 +203,17 @@ pub(crate) async fn get_public_rooms_filtered_helper(
         .rooms
         .directory
         .public_rooms()
+        .filter(|room_id| {
+            // Other servers only get to see rooms that are meant to be shared over federation
+            !for_federation
+                || room_id.as_ref().map_or(false, |room_id| {
+                    services()
+                        .rooms
+                        .directory
+                        .is_publicly_listable(room_id)
+                        .unwrap_or(false)
+                })
+        })
         .map(|room_id| {
             let room_id = room_id?;
 
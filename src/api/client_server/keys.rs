@@ -28,6 +28,8 @@ use std::collections::{BTreeMap, HashMap, HashSet};
 pub async fn upload_keys_route(
     body: Ruma<upload_keys::v3::Request>,
 ) -> Result<upload_keys::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
@@ -80,6 +82,8 @@ pub async fn get_keys_route(body: Ruma<get_keys::v3::Request>) -> Result<get_key
 pub async fn claim_keys_route(
     body: Ruma<claim_keys::v3::Request>,
 ) -> Result<claim_keys::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let response = claim_keys_helper(&body.one_time_keys).await?;
 
     Ok(response)
@@ -93,6 +97,8 @@ pub async fn claim_keys_route(
 pub async fn upload_signing_keys_route(
     body: Ruma<upload_signing_keys::v3::Request>,
 ) -> Result<upload_signing_keys::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     let sender_device = body.sender_device.as_ref().expect("user is authenticated");
 
@@ -144,6 +150,8 @@ pub async fn upload_signing_keys_route(
 pub async fn upload_signatures_route(
     body: Ruma<upload_signatures::v3::Request>,
 ) -> Result<upload_signatures::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     for (user_id, keys) in &body.signed_keys {
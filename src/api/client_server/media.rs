@@ -29,6 +29,8 @@ pub async fn get_media_config_route(
 pub async fn create_content_route(
     body: Ruma<create_content::v3::Request>,
 ) -> Result<create_content::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let mxc = format!(
         "mxc://{}/{}",
         services().globals.server_name(),
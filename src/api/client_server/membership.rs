@@ -14,16 +14,16 @@ use ruma::{
     events::{
         room::{
             join_rules::{AllowRule, JoinRule, RoomJoinRulesEventContent},
-            member::{MembershipState, RoomMemberEventContent},
+            member::MembershipState,
             power_levels::RoomPowerLevelsEventContent,
         },
-        RoomEventType, StateEventType,
+        StateEventType,
     },
     serde::Base64,
     state_res, CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId,
     OwnedServerName, OwnedUserId, RoomId, RoomVersionId, UserId,
 };
-use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
+use serde_json::value::RawValue as RawJsonValue;
 use std::{
     collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     sync::{Arc, RwLock},
@@ -32,7 +32,7 @@ use std::{
 use tracing::{debug, error, info, warn};
 
 use crate::{
-    service::pdu::{gen_event_id_canonical_json, PduBuilder},
+    service::{pdu::gen_event_id_canonical_json, rooms::state_cache::MembershipExtras},
     services, utils, Error, PduEvent, Result, Ruma,
 };
 
@@ -47,6 +47,8 @@ use super::get_alias_helper;
 pub async fn join_room_by_id_route(
     body: Ruma<join_room_by_id::v3::Request>,
 ) -> Result<join_room_by_id::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let mut servers = Vec::new(); // There is no body.server_name for /roomId/join
@@ -85,6 +87,8 @@ pub async fn join_room_by_id_route(
 pub async fn join_room_by_id_or_alias_route(
     body: Ruma<join_room_by_id_or_alias::v3::Request>,
 ) -> Result<join_room_by_id_or_alias::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_deref().expect("user is authenticated");
     let body = body.body;
 
@@ -137,6 +141,8 @@ pub async fn join_room_by_id_or_alias_route(
 pub async fn leave_room_route(
     body: Ruma<leave_room::v3::Request>,
 ) -> Result<leave_room::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     leave_room(sender_user, &body.room_id, body.reason.clone()).await?;
@@ -150,6 +156,8 @@ pub async fn leave_room_route(
 pub async fn invite_user_route(
     body: Ruma<invite_user::v3::Request>,
 ) -> Result<invite_user::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if let invite_user::v3::InvitationRecipient::UserId { user_id } = &body.recipient {
@@ -173,28 +181,25 @@ pub async fn invite_user_route(
 pub async fn kick_user_route(
     body: Ruma<kick_user::v3::Request>,
 ) -> Result<kick_user::v3::Response> {
-    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+    services().globals.ensure_writable()?;
 
-    let mut event: RoomMemberEventContent = serde_json::from_str(
-        services()
-            .rooms
-            .state_accessor
-            .room_state_get(
-                &body.room_id,
-                &StateEventType::RoomMember,
-                body.user_id.as_ref(),
-            )?
-            .ok_or(Error::BadRequest(
-                ErrorKind::BadState,
-                "Cannot kick member that's not in the room.",
-            ))?
-            .content
-            .get(),
-    )
-    .map_err(|_| Error::bad_database("Invalid member event in database."))?;
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-    event.membership = MembershipState::Leave;
-    event.reason = body.reason.clone();
+    if services()
+        .rooms
+        .state_accessor
+        .room_state_get(
+            &body.room_id,
+            &StateEventType::RoomMember,
+            body.user_id.as_ref(),
+        )?
+        .is_none()
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::BadState,
+            "Cannot kick member that's not in the room.",
+        ));
+    }
 
     let mutex_state = Arc::clone(
         services()
@@ -208,13 +213,15 @@ pub async fn kick_user_route(
     let state_lock = mutex_state.lock().await;
 
     services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content: to_raw_value(&event).expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some(body.user_id.to_string()),
-            redacts: None,
-        },
+        services().rooms.state_cache.build_membership_event(
+            &body.room_id,
+            &body.user_id,
+            MembershipState::Leave,
+            MembershipExtras {
+                reason: body.reason.clone(),
+                ..Default::default()
+            },
+        )?,
         sender_user,
         &body.room_id,
         &state_lock,
@@ -229,36 +236,9 @@ pub async fn kick_user_route(
 ///
 /// Tries to send a ban event into the room.
 pub async fn ban_user_route(body: Ruma<ban_user::v3::Request>) -> Result<ban_user::v3::Response> {
-    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+    services().globals.ensure_writable()?;
 
-    let event = services()
-        .rooms
-        .state_accessor
-        .room_state_get(
-            &body.room_id,
-            &StateEventType::RoomMember,
-            body.user_id.as_ref(),
-        )?
-        .map_or(
-            Ok(RoomMemberEventContent {
-                membership: MembershipState::Ban,
-                displayname: services().users.displayname(&body.user_id)?,
-                avatar_url: services().users.avatar_url(&body.user_id)?,
-                is_direct: None,
-                third_party_invite: None,
-                blurhash: services().users.blurhash(&body.user_id)?,
-                reason: body.reason.clone(),
-                join_authorized_via_users_server: None,
-            }),
-            |event| {
-                serde_json::from_str(event.content.get())
-                    .map(|event: RoomMemberEventContent| RoomMemberEventContent {
-                        membership: MembershipState::Ban,
-                        ..event
-                    })
-                    .map_err(|_| Error::bad_database("Invalid member event in database."))
-            },
-        )?;
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let mutex_state = Arc::clone(
         services()
@@ -272,13 +252,15 @@ pub async fn ban_user_route(body: Ruma<ban_user::v3::Request>) -> Result<ban_use
     let state_lock = mutex_state.lock().await;
 
     services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content: to_raw_value(&event).expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some(body.user_id.to_string()),
-            redacts: None,
-        },
+        services().rooms.state_cache.build_membership_event(
+            &body.room_id,
+            &body.user_id,
+            MembershipState::Ban,
+            MembershipExtras {
+                reason: body.reason.clone(),
+                ..Default::default()
+            },
+        )?,
         sender_user,
         &body.room_id,
         &state_lock,
@@ -295,28 +277,25 @@ pub async fn ban_user_route(body: Ruma<ban_user::v3::Request>) -> Result<ban_use
 pub async fn unban_user_route(
     body: Ruma<unban_user::v3::Request>,
 ) -> Result<unban_user::v3::Response> {
-    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
+    services().globals.ensure_writable()?;
 
-    let mut event: RoomMemberEventContent = serde_json::from_str(
-        services()
-            .rooms
-            .state_accessor
-            .room_state_get(
-                &body.room_id,
-                &StateEventType::RoomMember,
-                body.user_id.as_ref(),
-            )?
-            .ok_or(Error::BadRequest(
-                ErrorKind::BadState,
-                "Cannot unban a user who is not banned.",
-            ))?
-            .content
-            .get(),
-    )
-    .map_err(|_| Error::bad_database("Invalid member event in database."))?;
+    let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
-    event.membership = MembershipState::Leave;
-    event.reason = body.reason.clone();
+    if services()
+        .rooms
+        .state_accessor
+        .room_state_get(
+            &body.room_id,
+            &StateEventType::RoomMember,
+            body.user_id.as_ref(),
+        )?
+        .is_none()
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::BadState,
+            "Cannot unban a user who is not banned.",
+        ));
+    }
 
     let mutex_state = Arc::clone(
         services()
@@ -330,13 +309,15 @@ pub async fn unban_user_route(
     let state_lock = mutex_state.lock().await;
 
     services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content: to_raw_value(&event).expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some(body.user_id.to_string()),
-            redacts: None,
-        },
+        services().rooms.state_cache.build_membership_event(
+            &body.room_id,
+            &body.user_id,
+            MembershipState::Leave,
+            MembershipExtras {
+                reason: body.reason.clone(),
+                ..Default::default()
+            },
+        )?,
         sender_user,
         &body.room_id,
         &state_lock,
@@ -538,16 +519,22 @@ async fn join_room_by_id_helper(
         );
         join_event_stub.insert(
             "content".to_owned(),
-            to_canonical_value(RoomMemberEventContent {
-                membership: MembershipState::Join,
-                displayname: services().users.displayname(sender_user)?,
-                avatar_url: services().users.avatar_url(sender_user)?,
-                is_direct: None,
-                third_party_invite: None,
-                blurhash: services().users.blurhash(sender_user)?,
-                reason,
-                join_authorized_via_users_server,
-            })
+            to_canonical_value(
+                &*services()
+                    .rooms
+                    .state_cache
+                    .build_membership_event(
+                        room_id,
+                        sender_user,
+                        MembershipState::Join,
+                        MembershipExtras {
+                            reason,
+                            join_authorized_via_users_server,
+                            ..Default::default()
+                        },
+                    )?
+                    .content,
+            )
             .expect("event is valid, we just created it"),
         );
 
@@ -872,26 +859,20 @@ async fn join_room_by_id_helper(
             })
             .flatten();
 
-        let event = RoomMemberEventContent {
-            membership: MembershipState::Join,
-            displayname: services().users.displayname(sender_user)?,
-            avatar_url: services().users.avatar_url(sender_user)?,
-            is_direct: None,
-            third_party_invite: None,
-            blurhash: services().users.blurhash(sender_user)?,
-            reason: reason.clone(),
-            join_authorized_via_users_server: authorized_user,
-        };
+        let pdu_builder = services().rooms.state_cache.build_membership_event(
+            room_id,
+            sender_user,
+            MembershipState::Join,
+            MembershipExtras {
+                reason: reason.clone(),
+                join_authorized_via_users_server: authorized_user,
+                ..Default::default()
+            },
+        )?;
 
         // Try normal join first
         let error = match services().rooms.timeline.build_and_append_pdu(
-            PduBuilder {
-                event_type: RoomEventType::RoomMember,
-                content: to_raw_value(&event).expect("event is valid, we just created it"),
-                unsigned: None,
-                state_key: Some(sender_user.to_string()),
-                redacts: None,
-            },
+            pdu_builder,
             sender_user,
             room_id,
             &state_lock,
@@ -945,16 +926,22 @@ async fn join_room_by_id_helper(
             );
             join_event_stub.insert(
                 "content".to_owned(),
-                to_canonical_value(RoomMemberEventContent {
-                    membership: MembershipState::Join,
-                    displayname: services().users.displayname(sender_user)?,
-                    avatar_url: services().users.avatar_url(sender_user)?,
-                    is_direct: None,
-                    third_party_invite: None,
-                    blurhash: services().users.blurhash(sender_user)?,
-                    reason,
-                    join_authorized_via_users_server,
-                })
+                to_canonical_value(
+                    &*services()
+                        .rooms
+                        .state_cache
+                        .build_membership_event(
+                            room_id,
+                            sender_user,
+                            MembershipState::Join,
+                            MembershipExtras {
+                                reason,
+                                join_authorized_via_users_server,
+                                ..Default::default()
+                            },
+                        )?
+                        .content,
+                )
                 .expect("event is valid, we just created it"),
             );
 
@@ -1172,26 +1159,17 @@ pub(crate) async fn invite_helper<'a>(
             );
             let state_lock = mutex_state.lock().await;
 
-            let content = to_raw_value(&RoomMemberEventContent {
-                avatar_url: None,
-                displayname: None,
-                is_direct: Some(is_direct),
-                membership: MembershipState::Invite,
-                third_party_invite: None,
-                blurhash: None,
-                reason,
-                join_authorized_via_users_server: None,
-            })
-            .expect("member event is valid value");
-
             let (pdu, pdu_json) = services().rooms.timeline.create_hash_and_sign_event(
-                PduBuilder {
-                    event_type: RoomEventType::RoomMember,
-                    content,
-                    unsigned: None,
-                    state_key: Some(user_id.to_string()),
-                    redacts: None,
-                },
+                services().rooms.state_cache.build_membership_event(
+                    room_id,
+                    user_id,
+                    MembershipState::Invite,
+                    MembershipExtras {
+                        reason,
+                        is_direct: Some(is_direct),
+                        ..Default::default()
+                    },
+                )?,
                 sender_user,
                 room_id,
                 &state_lock,
@@ -1294,23 +1272,16 @@ pub(crate) async fn invite_helper<'a>(
     let state_lock = mutex_state.lock().await;
 
     services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content: to_raw_value(&RoomMemberEventContent {
-                membership: MembershipState::Invite,
-                displayname: services().users.displayname(user_id)?,
-                avatar_url: services().users.avatar_url(user_id)?,
-                is_direct: Some(is_direct),
-                third_party_invite: None,
-                blurhash: services().users.blurhash(user_id)?,
+        services().rooms.state_cache.build_membership_event(
+            room_id,
+            user_id,
+            MembershipState::Invite,
+            MembershipExtras {
                 reason,
-                join_authorized_via_users_server: None,
-            })
-            .expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some(user_id.to_string()),
-            redacts: None,
-        },
+                is_direct: Some(is_direct),
+                ..Default::default()
+            },
+        )?,
         sender_user,
         room_id,
         &state_lock,
@@ -1374,6 +1345,7 @@ pub async fn leave_room(user_id: &UserId, room_id: &RoomId, reason: Option<Strin
             MembershipState::Leave,
             user_id,
             last_state,
+            None,
             true,
         )?;
     } else {
@@ -1395,37 +1367,31 @@ pub async fn leave_room(user_id: &UserId, room_id: &RoomId, reason: Option<Strin
         )?;
 
         // Fix for broken rooms
-        let member_event = match member_event {
-            None => {
-                error!("Trying to leave a room you are not a member of.");
+        if member_event.is_none() {
+            error!("Trying to leave a room you are not a member of.");
 
-                services().rooms.state_cache.update_membership(
-                    room_id,
-                    user_id,
-                    MembershipState::Leave,
-                    user_id,
-                    None,
-                    true,
-                )?;
-                return Ok(());
-            }
-            Some(e) => e,
-        };
-
-        let mut event: RoomMemberEventContent = serde_json::from_str(member_event.content.get())
-            .map_err(|_| Error::bad_database("Invalid member event in database."))?;
-
-        event.membership = MembershipState::Leave;
-        event.reason = reason;
+            services().rooms.state_cache.update_membership(
+                room_id,
+                user_id,
+                MembershipState::Leave,
+                user_id,
+                None,
+                None,
+                true,
+            )?;
+            return Ok(());
+        }
 
         services().rooms.timeline.build_and_append_pdu(
-            PduBuilder {
-                event_type: RoomEventType::RoomMember,
-                content: to_raw_value(&event).expect("event is valid, we just created it"),
-                unsigned: None,
-                state_key: Some(user_id.to_string()),
-                redacts: None,
-            },
+            services().rooms.state_cache.build_membership_event(
+                room_id,
+                user_id,
+                MembershipState::Leave,
+                MembershipExtras {
+                    reason,
+                    ..Default::default()
+                },
+            )?,
             user_id,
             room_id,
             &state_lock,
@@ -27,6 +27,11 @@ pub async fn send_message_event_route(
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
     let sender_device = body.sender_device.as_deref();
 
+    // Admins can still talk to the admin bot, e.g. to leave maintenance mode again
+    if !services().users.is_admin(sender_user)? {
+        services().globals.ensure_writable()?;
+    }
+
     let mutex_state = Arc::clone(
         services()
             .globals
@@ -180,8 +185,15 @@ pub async fn get_message_events_route(
 
             let events_after: Vec<_> = events_after
                 .into_iter()
-                .map(|(_, pdu)| pdu.to_room_event())
-                .collect();
+                .map(|(_, mut pdu)| {
+                    services().rooms.timeline.enrich_unsigned(
+                        &mut pdu,
+                        sender_user,
+                        body.sender_device.as_deref(),
+                    )?;
+                    Ok(pdu.to_room_event())
+                })
+                .collect::<Result<_>>()?;
 
             resp.start = from.stringify();
             resp.end = next_token.map(|count| count.stringify());
@@ -229,8 +241,15 @@ pub async fn get_message_events_route(
 
             let events_before: Vec<_> = events_before
                 .into_iter()
-                .map(|(_, pdu)| pdu.to_room_event())
-                .collect();
+                .map(|(_, mut pdu)| {
+                    services().rooms.timeline.enrich_unsigned(
+                        &mut pdu,
+                        sender_user,
+                        body.sender_device.as_deref(),
+                    )?;
+                    Ok(pdu.to_room_event())
+                })
+                .collect::<Result<_>>()?;
 
             resp.start = from.stringify();
             resp.end = next_token.map(|count| count.stringify());
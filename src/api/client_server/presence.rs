@@ -1,13 +1,20 @@
 use crate::{services, utils, Result, Ruma};
-use ruma::api::client::presence::{get_presence, set_presence};
+use ruma::{
+    api::client::presence::{get_presence, set_presence},
+    presence::PresenceState,
+};
 use std::time::Duration;
 
 /// # `PUT /_matrix/client/r0/presence/{userId}/status`
 ///
 /// Sets the presence state of the sender user.
+///
+/// - Is a NOOP if presence is disabled
 pub async fn set_presence_route(
     body: Ruma<set_presence::v3::Request>,
 ) -> Result<set_presence::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     for room_id in services().rooms.state_cache.rooms_joined(sender_user) {
@@ -42,11 +49,21 @@ pub async fn set_presence_route(
 /// Gets the presence state of the given user.
 ///
 /// - Only works if you share a room with the user
+/// - Always returns offline if presence is disabled
 pub async fn get_presence_route(
     body: Ruma<get_presence::v3::Request>,
 ) -> Result<get_presence::v3::Response> {
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
+    if !services().globals.presence_enabled() {
+        return Ok(get_presence::v3::Response {
+            status_msg: None,
+            currently_active: None,
+            last_active_ago: None,
+            presence: PresenceState::Offline,
+        });
+    }
+
     let mut presence_event = None;
 
     for room_id in services()
@@ -22,6 +22,8 @@ use std::sync::Arc;
 pub async fn set_displayname_route(
     body: Ruma<set_display_name::v3::Request>,
 ) -> Result<set_display_name::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     services()
@@ -153,6 +155,8 @@ pub async fn get_displayname_route(
 pub async fn set_avatar_url_route(
     body: Ruma<set_avatar_url::v3::Request>,
 ) -> Result<set_avatar_url::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     services()
@@ -18,6 +18,8 @@ use std::collections::BTreeMap;
 pub async fn set_read_marker_route(
     body: Ruma<set_read_marker::v3::Request>,
 ) -> Result<set_read_marker::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if let Some(fully_read) = &body.fully_read {
@@ -101,6 +103,8 @@ pub async fn set_read_marker_route(
 pub async fn create_receipt_route(
     body: Ruma<create_receipt::v3::Request>,
 ) -> Result<create_receipt::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if matches!(
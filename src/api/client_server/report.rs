@@ -12,6 +12,8 @@ use ruma::{
 pub async fn report_event_route(
     body: Ruma<report_content::v3::Request>,
 ) -> Result<report_content::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let pdu = match services().rooms.timeline.get_pdu(&body.event_id)? {
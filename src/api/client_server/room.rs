@@ -1,5 +1,7 @@
 use crate::{
-    api::client_server::invite_helper, service::pdu::PduBuilder, services, Error, Result, Ruma,
+    api::client_server::invite_helper,
+    service::{pdu::PduBuilder, rooms::state_cache::MembershipExtras},
+    services, Error, Result, Ruma,
 };
 use ruma::{
     api::client::{
@@ -13,7 +15,7 @@ use ruma::{
             guest_access::{GuestAccess, RoomGuestAccessEventContent},
             history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
             join_rules::{JoinRule, RoomJoinRulesEventContent},
-            member::{MembershipState, RoomMemberEventContent},
+            member::MembershipState,
             name::RoomNameEventContent,
             power_levels::RoomPowerLevelsEventContent,
             tombstone::RoomTombstoneEventContent,
@@ -27,7 +29,7 @@ use ruma::{
 };
 use serde_json::{json, value::to_raw_value};
 use std::{cmp::max, collections::BTreeMap, sync::Arc};
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
 /// # `POST /_matrix/client/r0/createRoom`
 ///
@@ -50,6 +52,8 @@ pub async fn create_room_route(
 ) -> Result<create_room::v3::Response> {
     use create_room::v3::RoomPreset;
 
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let room_id = RoomId::new(services().globals.server_name());
@@ -172,228 +176,274 @@ pub async fn create_room_route(
         ));
     }
 
-    // 1. The room create event
-    services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomCreate,
-            content: to_raw_value(&content).expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some("".to_owned()),
-            redacts: None,
-        },
-        sender_user,
-        &room_id,
-        &state_lock,
-    )?;
+    // If anything fails before the room is complete, remove what was already created instead of
+    // leaving a half-created room behind
+    run_with_rollback(
+        |created| {
+            // 1. The room create event
+            created.push(services().rooms.timeline.build_and_append_pdu(
+                PduBuilder {
+                    event_type: RoomEventType::RoomCreate,
+                    content: to_raw_value(&content).expect("event is valid, we just created it"),
+                    unsigned: None,
+                    state_key: Some("".to_owned()),
+                    redacts: None,
+                },
+                sender_user,
+                &room_id,
+                &state_lock,
+            )?);
+
+            // 2. Let the room creator join
+            created.push(services().rooms.timeline.build_and_append_pdu(
+                services().rooms.state_cache.build_membership_event(
+                    &room_id,
+                    sender_user,
+                    MembershipState::Join,
+                    MembershipExtras {
+                        is_direct: Some(body.is_direct),
+                        ..Default::default()
+                    },
+                )?,
+                sender_user,
+                &room_id,
+                &state_lock,
+            )?);
+
+            // 3. Power levels
+
+            // Figure out preset. We need it for preset specific events
+            let preset = body.preset.clone().unwrap_or(match &body.visibility {
+                room::Visibility::Private => RoomPreset::PrivateChat,
+                room::Visibility::Public => RoomPreset::PublicChat,
+                _ => RoomPreset::PrivateChat, // Room visibility should not be custom
+            });
+
+            let mut users = BTreeMap::new();
+            users.insert(sender_user.clone(), int!(100));
+
+            if preset == RoomPreset::TrustedPrivateChat {
+                for invite_ in &body.invite {
+                    users.insert(invite_.clone(), int!(100));
+                }
+            }
 
-    // 2. Let the room creator join
-    services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content: to_raw_value(&RoomMemberEventContent {
-                membership: MembershipState::Join,
-                displayname: services().users.displayname(sender_user)?,
-                avatar_url: services().users.avatar_url(sender_user)?,
-                is_direct: Some(body.is_direct),
-                third_party_invite: None,
-                blurhash: services().users.blurhash(sender_user)?,
-                reason: None,
-                join_authorized_via_users_server: None,
+            let mut power_levels_content = serde_json::to_value(RoomPowerLevelsEventContent {
+                users,
+                ..Default::default()
             })
-            .expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some(sender_user.to_string()),
-            redacts: None,
-        },
-        sender_user,
-        &room_id,
-        &state_lock,
-    )?;
-
-    // 3. Power levels
+            .expect("event is valid, we just created it");
+
+            if let Some(power_level_content_override) = &body.power_level_content_override {
+                let json: JsonObject = serde_json::from_str(
+                    power_level_content_override.json().get(),
+                )
+                .map_err(|_| {
+                    Error::BadRequest(ErrorKind::BadJson, "Invalid power_level_content_override.")
+                })?;
+
+                for (key, value) in json {
+                    power_levels_content[key] = value;
+                }
+            }
 
-    // Figure out preset. We need it for preset specific events
-    let preset = body.preset.clone().unwrap_or(match &body.visibility {
-        room::Visibility::Private => RoomPreset::PrivateChat,
-        room::Visibility::Public => RoomPreset::PublicChat,
-        _ => RoomPreset::PrivateChat, // Room visibility should not be custom
-    });
+            created.push(
+                services().rooms.timeline.build_and_append_pdu(
+                    PduBuilder {
+                        event_type: RoomEventType::RoomPowerLevels,
+                        content: to_raw_value(&power_levels_content)
+                            .expect("to_raw_value always works on serde_json::Value"),
+                        unsigned: None,
+                        state_key: Some("".to_owned()),
+                        redacts: None,
+                    },
+                    sender_user,
+                    &room_id,
+                    &state_lock,
+                )?,
+            );
 
-    let mut users = BTreeMap::new();
-    users.insert(sender_user.clone(), int!(100));
+            // 4. Canonical room alias
+            if let Some(room_alias_id) = &alias {
+                created.push(
+                    services().rooms.timeline.build_and_append_pdu(
+                        PduBuilder {
+                            event_type: RoomEventType::RoomCanonicalAlias,
+                            content: to_raw_value(&RoomCanonicalAliasEventContent {
+                                alias: Some(room_alias_id.to_owned()),
+                                alt_aliases: vec![],
+                            })
+                            .expect("We checked that alias earlier, it must be fine"),
+                            unsigned: None,
+                            state_key: Some("".to_owned()),
+                            redacts: None,
+                        },
+                        sender_user,
+                        &room_id,
+                        &state_lock,
+                    )?,
+                );
+            }
 
-    if preset == RoomPreset::TrustedPrivateChat {
-        for invite_ in &body.invite {
-            users.insert(invite_.clone(), int!(100));
-        }
-    }
+            // 5. Events set by preset
+
+            // 5.1 Join Rules
+            created.push(
+                services().rooms.timeline.build_and_append_pdu(
+                    PduBuilder {
+                        event_type: RoomEventType::RoomJoinRules,
+                        content: to_raw_value(&RoomJoinRulesEventContent::new(match preset {
+                            RoomPreset::PublicChat => JoinRule::Public,
+                            // according to spec "invite" is the default
+                            _ => JoinRule::Invite,
+                        }))
+                        .expect("event is valid, we just created it"),
+                        unsigned: None,
+                        state_key: Some("".to_owned()),
+                        redacts: None,
+                    },
+                    sender_user,
+                    &room_id,
+                    &state_lock,
+                )?,
+            );
 
-    let mut power_levels_content = serde_json::to_value(RoomPowerLevelsEventContent {
-        users,
-        ..Default::default()
-    })
-    .expect("event is valid, we just created it");
+            // 5.2 History Visibility
+            created.push(
+                services().rooms.timeline.build_and_append_pdu(
+                    PduBuilder {
+                        event_type: RoomEventType::RoomHistoryVisibility,
+                        content: to_raw_value(&RoomHistoryVisibilityEventContent::new(
+                            HistoryVisibility::Shared,
+                        ))
+                        .expect("event is valid, we just created it"),
+                        unsigned: None,
+                        state_key: Some("".to_owned()),
+                        redacts: None,
+                    },
+                    sender_user,
+                    &room_id,
+                    &state_lock,
+                )?,
+            );
 
-    if let Some(power_level_content_override) = &body.power_level_content_override {
-        let json: JsonObject = serde_json::from_str(power_level_content_override.json().get())
-            .map_err(|_| {
-                Error::BadRequest(ErrorKind::BadJson, "Invalid power_level_content_override.")
-            })?;
+            // 5.3 Guest Access
+            created.push(
+                services().rooms.timeline.build_and_append_pdu(
+                    PduBuilder {
+                        event_type: RoomEventType::RoomGuestAccess,
+                        content: to_raw_value(&RoomGuestAccessEventContent::new(match preset {
+                            RoomPreset::PublicChat => GuestAccess::Forbidden,
+                            _ => GuestAccess::CanJoin,
+                        }))
+                        .expect("event is valid, we just created it"),
+                        unsigned: None,
+                        state_key: Some("".to_owned()),
+                        redacts: None,
+                    },
+                    sender_user,
+                    &room_id,
+                    &state_lock,
+                )?,
+            );
 
-        for (key, value) in json {
-            power_levels_content[key] = value;
-        }
-    }
+            // 6. Events listed in initial_state
+            for event in &body.initial_state {
+                let mut pdu_builder = event.deserialize_as::<PduBuilder>().map_err(|e| {
+                    warn!("Invalid initial state event: {:?}", e);
+                    Error::BadRequest(ErrorKind::InvalidParam, "Invalid initial state event.")
+                })?;
 
-    services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomPowerLevels,
-            content: to_raw_value(&power_levels_content)
-                .expect("to_raw_value always works on serde_json::Value"),
-            unsigned: None,
-            state_key: Some("".to_owned()),
-            redacts: None,
-        },
-        sender_user,
-        &room_id,
-        &state_lock,
-    )?;
+                // Implicit state key defaults to ""
+                pdu_builder.state_key.get_or_insert_with(|| "".to_owned());
 
-    // 4. Canonical room alias
-    if let Some(room_alias_id) = &alias {
-        services().rooms.timeline.build_and_append_pdu(
-            PduBuilder {
-                event_type: RoomEventType::RoomCanonicalAlias,
-                content: to_raw_value(&RoomCanonicalAliasEventContent {
-                    alias: Some(room_alias_id.to_owned()),
-                    alt_aliases: vec![],
-                })
-                .expect("We checked that alias earlier, it must be fine"),
-                unsigned: None,
-                state_key: Some("".to_owned()),
-                redacts: None,
-            },
-            sender_user,
-            &room_id,
-            &state_lock,
-        )?;
-    }
+                // Silently skip encryption events if they are not allowed
+                if pdu_builder.event_type == RoomEventType::RoomEncryption
+                    && !services().globals.allow_encryption()
+                {
+                    continue;
+                }
 
-    // 5. Events set by preset
+                created.push(services().rooms.timeline.build_and_append_pdu(
+                    pdu_builder,
+                    sender_user,
+                    &room_id,
+                    &state_lock,
+                )?);
+            }
 
-    // 5.1 Join Rules
-    services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomJoinRules,
-            content: to_raw_value(&RoomJoinRulesEventContent::new(match preset {
-                RoomPreset::PublicChat => JoinRule::Public,
-                // according to spec "invite" is the default
-                _ => JoinRule::Invite,
-            }))
-            .expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some("".to_owned()),
-            redacts: None,
-        },
-        sender_user,
-        &room_id,
-        &state_lock,
-    )?;
+            // 7. Events implied by name and topic
+            if let Some(name) = &body.name {
+                created.push(
+                    services().rooms.timeline.build_and_append_pdu(
+                        PduBuilder {
+                            event_type: RoomEventType::RoomName,
+                            content: to_raw_value(&RoomNameEventContent::new(Some(name.clone())))
+                                .expect("event is valid, we just created it"),
+                            unsigned: None,
+                            state_key: Some("".to_owned()),
+                            redacts: None,
+                        },
+                        sender_user,
+                        &room_id,
+                        &state_lock,
+                    )?,
+                );
+            }
 
-    // 5.2 History Visibility
-    services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomHistoryVisibility,
-            content: to_raw_value(&RoomHistoryVisibilityEventContent::new(
-                HistoryVisibility::Shared,
-            ))
-            .expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some("".to_owned()),
-            redacts: None,
-        },
-        sender_user,
-        &room_id,
-        &state_lock,
-    )?;
+            if let Some(topic) = &body.topic {
+                created.push(
+                    services().rooms.timeline.build_and_append_pdu(
+                        PduBuilder {
+                            event_type: RoomEventType::RoomTopic,
+                            content: to_raw_value(&RoomTopicEventContent {
+                                topic: topic.clone(),
+                            })
+                            .expect("event is valid, we just created it"),
+                            unsigned: None,
+                            state_key: Some("".to_owned()),
+                            redacts: None,
+                        },
+                        sender_user,
+                        &room_id,
+                        &state_lock,
+                    )?,
+                );
+            }
 
-    // 5.3 Guest Access
-    services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomGuestAccess,
-            content: to_raw_value(&RoomGuestAccessEventContent::new(match preset {
-                RoomPreset::PublicChat => GuestAccess::Forbidden,
-                _ => GuestAccess::CanJoin,
-            }))
-            .expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some("".to_owned()),
-            redacts: None,
-        },
-        sender_user,
-        &room_id,
-        &state_lock,
-    )?;
+            // Homeserver specific stuff
+            if let Some(alias) = &alias {
+                services().rooms.alias.set_alias(alias, &room_id)?;
+            }
 
-    // 6. Events listed in initial_state
-    for event in &body.initial_state {
-        let mut pdu_builder = event.deserialize_as::<PduBuilder>().map_err(|e| {
-            warn!("Invalid initial state event: {:?}", e);
-            Error::BadRequest(ErrorKind::InvalidParam, "Invalid initial state event.")
-        })?;
-
-        // Implicit state key defaults to ""
-        pdu_builder.state_key.get_or_insert_with(|| "".to_owned());
-
-        // Silently skip encryption events if they are not allowed
-        if pdu_builder.event_type == RoomEventType::RoomEncryption
-            && !services().globals.allow_encryption()
-        {
-            continue;
-        }
+            if body.visibility == room::Visibility::Public {
+                services().rooms.directory.set_public(&room_id)?;
+            }
 
-        services().rooms.timeline.build_and_append_pdu(
-            pdu_builder,
-            sender_user,
-            &room_id,
-            &state_lock,
-        )?;
-    }
+            Ok(())
+        },
+        |created| {
+            warn!("Failed to create room {}, rolling back", room_id);
 
-    // 7. Events implied by name and topic
-    if let Some(name) = &body.name {
-        services().rooms.timeline.build_and_append_pdu(
-            PduBuilder {
-                event_type: RoomEventType::RoomName,
-                content: to_raw_value(&RoomNameEventContent::new(Some(name.clone())))
-                    .expect("event is valid, we just created it"),
-                unsigned: None,
-                state_key: Some("".to_owned()),
-                redacts: None,
-            },
-            sender_user,
-            &room_id,
-            &state_lock,
-        )?;
-    }
+            if let Some(alias) = &alias {
+                if services()
+                    .rooms
+                    .alias
+                    .resolve_local_alias(alias)?
+                    .as_deref()
+                    == Some(&*room_id)
+                {
+                    services().rooms.alias.remove_alias(alias)?;
+                }
+            }
+            services().rooms.directory.set_not_public(&room_id)?;
 
-    if let Some(topic) = &body.topic {
-        services().rooms.timeline.build_and_append_pdu(
-            PduBuilder {
-                event_type: RoomEventType::RoomTopic,
-                content: to_raw_value(&RoomTopicEventContent {
-                    topic: topic.clone(),
-                })
-                .expect("event is valid, we just created it"),
-                unsigned: None,
-                state_key: Some("".to_owned()),
-                redacts: None,
-            },
-            sender_user,
-            &room_id,
-            &state_lock,
-        )?;
-    }
+            services()
+                .rooms
+                .state
+                .purge_room(&room_id, created, &state_lock)
+        },
+    )?;
 
     // 8. Events implied by invite (and TODO: invite_3pid)
     drop(state_lock);
@@ -401,15 +451,6 @@ pub async fn create_room_route(
         let _ = invite_helper(sender_user, user_id, &room_id, None, body.is_direct).await;
     }
 
-    // Homeserver specific stuff
-    if let Some(alias) = alias {
-        services().rooms.alias.set_alias(&alias, &room_id)?;
-    }
-
-    if body.visibility == room::Visibility::Public {
-        services().rooms.directory.set_public(&room_id)?;
-    }
-
     info!("{} created a room", sender_user);
 
     Ok(create_room::v3::Response::new(room_id))
@@ -442,6 +483,13 @@ pub async fn get_room_event_route(
         ));
     }
 
+    let mut event = (*event).clone();
+    services().rooms.timeline.enrich_unsigned(
+        &mut event,
+        sender_user,
+        body.sender_device.as_deref(),
+    )?;
+
     Ok(get_room_event::v3::Response {
         event: event.to_room_event(),
     })
@@ -491,6 +539,8 @@ pub async fn get_room_aliases_route(
 pub async fn upgrade_room_route(
     body: Ruma<upgrade_room::v3::Request>,
 ) -> Result<upgrade_room::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if !services()
@@ -622,23 +672,12 @@ pub async fn upgrade_room_route(
 
     // Join the new room
     services().rooms.timeline.build_and_append_pdu(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content: to_raw_value(&RoomMemberEventContent {
-                membership: MembershipState::Join,
-                displayname: services().users.displayname(sender_user)?,
-                avatar_url: services().users.avatar_url(sender_user)?,
-                is_direct: None,
-                third_party_invite: None,
-                blurhash: services().users.blurhash(sender_user)?,
-                reason: None,
-                join_authorized_via_users_server: None,
-            })
-            .expect("event is valid, we just created it"),
-            unsigned: None,
-            state_key: Some(sender_user.to_string()),
-            redacts: None,
-        },
+        services().rooms.state_cache.build_membership_event(
+            &replacement_room,
+            sender_user,
+            MembershipState::Join,
+            MembershipExtras::default(),
+        )?,
         sender_user,
         &replacement_room,
         &state_lock,
@@ -733,3 +772,87 @@ pub async fn upgrade_room_route(
     // Return the replacement room id
     Ok(upgrade_room::v3::Response { replacement_room })
 }
+
+/// Runs `steps`, which records everything it creates. If a step fails, `rollback` is given the
+/// created items to remove them again, and the original error is returned.
+fn run_with_rollback<T>(
+    steps: impl FnOnce(&mut Vec<T>) -> Result<()>,
+    rollback: impl FnOnce(&[T]) -> Result<()>,
+) -> Result<()> {
+    let mut created = Vec::new();
+
+    if let Err(e) = steps(&mut created) {
+        if let Err(rollback_error) = rollback(&created) {
+            error!("Failed to roll back: {}", rollback_error);
+        }
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use ruma::{api::client::room::create_room, serde::Raw, RoomAliasId};
+    use serde_json::{json, value::to_raw_value};
+
+    use super::create_room_route;
+    use crate::{services, testing, utils};
+
+    #[tokio::test]
+    async fn failure_mid_create_leaves_no_room_behind() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let alias_localpart = utils::random_string(8).to_lowercase();
+        let rooms_before: HashSet<_> = services()
+            .rooms
+            .metadata
+            .iter_ids()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let mut body = create_room::v3::Request::new();
+        body.name = Some("Doomed".to_owned());
+        body.room_alias_name = Some(alias_localpart.clone());
+        // The create, member, power levels and join rules events are sent before this one fails
+        body.initial_state = vec![Raw::from_json(
+            to_raw_value(&json!({ "type": "m.room.topic" })).unwrap(),
+        )];
+
+        assert!(create_room_route(testing::request(&alice, body))
+            .await
+            .is_err());
+
+        let rooms_after: HashSet<_> = services()
+            .rooms
+            .metadata
+            .iter_ids()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rooms_before, rooms_after);
+        assert_eq!(
+            services()
+                .rooms
+                .alias
+                .resolve_local_alias(
+                    &RoomAliasId::parse(format!(
+                        "#{}:{}",
+                        alias_localpart,
+                        services().globals.server_name()
+                    ))
+                    .unwrap()
+                )
+                .unwrap(),
+            None
+        );
+        assert_eq!(services().rooms.state_cache.rooms_joined(&alice).count(), 0);
+        assert!(services()
+            .rooms
+            .user
+            .rooms_changed_since(&alice, 0)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -19,6 +19,7 @@ use ruma::{
     serde::Raw,
     DeviceId, OwnedDeviceId, OwnedUserId, RoomId, UserId,
 };
+use serde_json::json;
 use std::{
     collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
     sync::Arc,
@@ -217,19 +218,138 @@ async fn sync_helper(
             .filter_map(|r| r.ok()),
     );
 
-    let all_joined_rooms = services()
+    let mut all_joined_rooms = services()
         .rooms
         .state_cache
         .rooms_joined(&sender_user)
-        .collect::<Vec<_>>();
+        .collect::<Result<Vec<_>>>()?;
+
+    // Users in very many rooms only get the most recently active ones on an initial sync. The
+    // rest are sent to the device with their full state once they see activity.
+    let mut omitted_rooms = Vec::new();
+    if body.since.is_none() {
+        if let Some(limit) = services().globals.max_initial_sync_rooms() {
+            all_joined_rooms = services()
+                .rooms
+                .user
+                .sort_by_last_activity(&sender_user, all_joined_rooms)?;
+            omitted_rooms = all_joined_rooms.split_off(all_joined_rooms.len().min(limit as usize));
+        }
+
+        services().rooms.user.set_initial_sync_omitted(
+            &sender_user,
+            &sender_device,
+            &omitted_rooms,
+        )?;
+    }
+
+    // On an incremental sync only rooms that changed since `since` need to be loaded
+    let changed_rooms = if body.since.is_some() && !full_state {
+        Some(
+            services()
+                .rooms
+                .user
+                .rooms_changed_since(&sender_user, since)?
+                .into_iter()
+                .collect::<HashSet<_>>(),
+        )
+    } else {
+        None
+    };
+
     for room_id in all_joined_rooms {
-        let room_id = room_id?;
+        // Take presence updates from this room
+        for (user_id, presence) in services()
+            .rooms
+            .edus
+            .presence
+            .presence_since(&room_id, since)?
+        {
+            match presence_updates.entry(user_id) {
+                Entry::Vacant(v) => {
+                    v.insert(presence);
+                }
+                Entry::Occupied(mut o) => {
+                    let p = o.get_mut();
+
+                    // Update existing presence event with more info
+                    p.content.presence = presence.content.presence;
+                    if let Some(status_msg) = presence.content.status_msg {
+                        p.content.status_msg = Some(status_msg);
+                    }
+                    if let Some(last_active_ago) = presence.content.last_active_ago {
+                        p.content.last_active_ago = Some(last_active_ago);
+                    }
+                    if let Some(displayname) = presence.content.displayname {
+                        p.content.displayname = Some(displayname);
+                    }
+                    if let Some(avatar_url) = presence.content.avatar_url {
+                        p.content.avatar_url = Some(avatar_url);
+                    }
+                    if let Some(currently_active) = presence.content.currently_active {
+                        p.content.currently_active = Some(currently_active);
+                    }
+                }
+            }
+        }
+
+        let (room_since, room_sincecount) = if services().rooms.user.was_omitted_from_initial_sync(
+            &sender_user,
+            &sender_device,
+            &room_id,
+        )? {
+            if services()
+                .rooms
+                .timeline
+                .last_timeline_count(&sender_user, &room_id)?
+                <= sincecount
+            {
+                continue;
+            }
+
+            // The device has never seen this room, so it gets it like in an initial sync
+            services().rooms.user.remove_initial_sync_omitted(
+                &sender_user,
+                &sender_device,
+                &room_id,
+            )?;
+            (0, PduCount::Normal(0))
+        } else {
+            if let Some(changed_rooms) = &changed_rooms {
+                if !changed_rooms.contains(&room_id)
+                    && services().rooms.edus.typing.last_typing_update(&room_id)? <= since
+                {
+                    // The next sync diffs the room state against the one at this token
+                    if let Some(shortstatehash) =
+                        services().rooms.state.get_room_shortstatehash(&room_id)?
+                    {
+                        services().rooms.user.associate_token_shortstatehash(
+                            &room_id,
+                            next_batch,
+                            shortstatehash,
+                        )?;
+                    }
+
+                    // Nothing to send for the room itself, but members may have new device keys
+                    device_list_updates.extend(
+                        services()
+                            .users
+                            .keys_changed(room_id.as_ref(), since, None)
+                            .filter_map(|r| r.ok()),
+                    );
+                    continue;
+                }
+            }
+
+            (since, sincecount)
+        };
+
         if let Ok(joined_room) = load_joined_room(
             &sender_user,
             &sender_device,
             &room_id,
-            since,
-            sincecount,
+            room_since,
+            room_sincecount,
             next_batch,
             next_batchcount,
             lazy_load_enabled,
@@ -243,41 +363,6 @@ async fn sync_helper(
             if !joined_room.is_empty() {
                 joined_rooms.insert(room_id.clone(), joined_room);
             }
-
-            // Take presence updates from this room
-            for (user_id, presence) in services()
-                .rooms
-                .edus
-                .presence
-                .presence_since(&room_id, since)?
-            {
-                match presence_updates.entry(user_id) {
-                    Entry::Vacant(v) => {
-                        v.insert(presence);
-                    }
-                    Entry::Occupied(mut o) => {
-                        let p = o.get_mut();
-
-                        // Update existing presence event with more info
-                        p.content.presence = presence.content.presence;
-                        if let Some(status_msg) = presence.content.status_msg {
-                            p.content.status_msg = Some(status_msg);
-                        }
-                        if let Some(last_active_ago) = presence.content.last_active_ago {
-                            p.content.last_active_ago = Some(last_active_ago);
-                        }
-                        if let Some(displayname) = presence.content.displayname {
-                            p.content.displayname = Some(displayname);
-                        }
-                        if let Some(avatar_url) = presence.content.avatar_url {
-                            p.content.avatar_url = Some(avatar_url);
-                        }
-                        if let Some(currently_active) = presence.content.currently_active {
-                            p.content.currently_active = Some(currently_active);
-                        }
-                    }
-                }
-            }
         }
     }
 
@@ -488,6 +573,21 @@ async fn sync_helper(
         .users
         .remove_to_device_events(&sender_user, &sender_device, since)?;
 
+    let mut account_data = services()
+        .account_data
+        .global_account_data_sync(&sender_user, since)?;
+    if !omitted_rooms.is_empty() {
+        // Clients that don't know this event just ignore it
+        account_data.push(
+            Raw::new(&json!({
+                "type": "rs.conduit.partial_initial_sync",
+                "content": { "omitted_rooms": omitted_rooms.len() },
+            }))
+            .expect("json is valid")
+            .cast(),
+        );
+    }
+
     let response = sync_events::v3::Response {
         next_batch: next_batch_string,
         rooms: Rooms {
@@ -503,16 +603,7 @@ async fn sync_helper(
                 .collect(),
         },
         account_data: GlobalAccountData {
-            events: services()
-                .account_data
-                .changes_since(None, &sender_user, since)?
-                .into_iter()
-                .filter_map(|(_, v)| {
-                    serde_json::from_str(v.json().get())
-                        .map_err(|_| Error::bad_database("Invalid account event in database."))
-                        .ok()
-                })
-                .collect(),
+            events: account_data,
         },
         device_lists: DeviceLists {
             changed: device_list_updates.into_iter().collect(),
@@ -1056,9 +1147,16 @@ async fn load_joined_room(
         })?;
 
     let room_events: Vec<_> = timeline_pdus
-        .iter()
-        .map(|(_, pdu)| pdu.to_sync_room_event())
-        .collect();
+        .into_iter()
+        .map(|(_, mut pdu)| {
+            services().rooms.timeline.enrich_unsigned(
+                &mut pdu,
+                sender_user,
+                Some(sender_device),
+            )?;
+            Ok(pdu.to_sync_room_event())
+        })
+        .collect::<Result<_>>()?;
 
     let mut edus: Vec<_> = services()
         .rooms
@@ -16,6 +16,8 @@ use std::collections::BTreeMap;
 pub async fn update_tag_route(
     body: Ruma<create_tag::v3::Request>,
 ) -> Result<create_tag::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let event = services().account_data.get(
@@ -60,6 +62,8 @@ pub async fn update_tag_route(
 pub async fn delete_tag_route(
     body: Ruma<delete_tag::v3::Request>,
 ) -> Result<delete_tag::v3::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     let event = services().account_data.get(
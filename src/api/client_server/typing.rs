@@ -9,6 +9,8 @@ pub async fn create_typing_event_route(
 ) -> Result<create_typing_event::v3::Response> {
     use create_typing_event::v3::Typing;
 
+    services().globals.ensure_writable()?;
+
     let sender_user = body.sender_user.as_ref().expect("user is authenticated");
 
     if !services()
@@ -1,6 +1,6 @@
 use crate::{
     api::client_server::{self, claim_keys_helper, get_keys_helper},
-    service::pdu::{gen_event_id_canonical_json, PduBuilder},
+    service::{pdu::gen_event_id_canonical_json, rooms::state_cache::MembershipExtras},
     services, utils, Error, PduEvent, Result, Ruma,
 };
 use axum::{response::IntoResponse, Json};
@@ -18,6 +18,7 @@ use ruma::{
             discovery::{get_server_keys, get_server_version, ServerSigningKeys, VerifyKey},
             event::{get_event, get_missing_events, get_room_state, get_room_state_ids},
             keys::{claim_keys, get_keys},
+            knock::{create_knock_event_template, send_knock},
             membership::{
                 create_invite,
                 create_join_event::{self, RoomState},
@@ -32,20 +33,20 @@ use ruma::{
         EndpointError, IncomingResponse, MatrixVersion, OutgoingRequest, OutgoingResponse,
         SendAccessToken,
     },
-    directory::{Filter, RoomNetwork},
+    directory::Filter,
     events::{
         receipt::{ReceiptEvent, ReceiptEventContent, ReceiptType},
         room::{
             join_rules::{JoinRule, RoomJoinRulesEventContent},
-            member::{MembershipState, RoomMemberEventContent},
+            member::MembershipState,
         },
-        RoomEventType, StateEventType,
+        AnyStrippedStateEvent, RoomEventType, StateEventType,
     },
     serde::{Base64, JsonObject, Raw},
     to_device::DeviceIdOrAllDevices,
     uint, user_id, CanonicalJsonObject, CanonicalJsonValue, EventId, MilliSecondsSinceUnixEpoch,
     OwnedEventId, OwnedRoomId, OwnedServerName, OwnedServerSigningKeyId, OwnedUserId, RoomId,
-    ServerName,
+    RoomVersionId, ServerName, UserId,
 };
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
 use std::{
@@ -589,6 +590,9 @@ pub async fn get_server_keys_deprecated_route() -> impl IntoResponse {
 /// # `POST /_matrix/federation/v1/publicRooms`
 ///
 /// Lists the public rooms on this server.
+///
+/// - Only rooms that are publicly listable over federation are returned
+/// - Third party networks are not supported and yield no rooms
 pub async fn get_public_rooms_filtered_route(
     body: Ruma<get_public_rooms_filtered::v1::Request>,
 ) -> Result<get_public_rooms_filtered::v1::Response> {
@@ -602,6 +606,7 @@ pub async fn get_public_rooms_filtered_route(
         body.since.as_deref(),
         &body.filter,
         &body.room_network,
+        true,
     )
     .await?;
 
@@ -616,6 +621,9 @@ pub async fn get_public_rooms_filtered_route(
 /// # `GET /_matrix/federation/v1/publicRooms`
 ///
 /// Lists the public rooms on this server.
+///
+/// - Only rooms that are publicly listable over federation are returned
+/// - Third party networks are not supported and yield no rooms
 pub async fn get_public_rooms_route(
     body: Ruma<get_public_rooms::v1::Request>,
 ) -> Result<get_public_rooms::v1::Response> {
@@ -628,7 +636,8 @@ pub async fn get_public_rooms_route(
         body.limit,
         body.since.as_deref(),
         &Filter::default(),
-        &RoomNetwork::Matrix,
+        &body.room_network,
+        true,
     )
     .await?;
 
@@ -681,6 +690,9 @@ pub async fn send_transaction_message_route(
         return Err(Error::bad_config("Federation is disabled."));
     }
 
+    // Remote servers retry the transaction once we are out of maintenance
+    services().globals.ensure_writable()?;
+
     let sender_servername = body
         .sender_servername
         .as_ref()
@@ -766,6 +778,7 @@ pub async fn send_transaction_message_route(
         .filter_map(|edu| serde_json::from_str::<Edu>(edu.json().get()).ok())
     {
         match edu {
+            // Presence EDUs are dropped without processing
             Edu::Presence(_) => {}
             Edu::Receipt(receipt) => {
                 for (room_id, room_updates) in receipt.receipts {
@@ -1426,26 +1439,13 @@ pub async fn create_join_event_template_route(
         ));
     }
 
-    let content = to_raw_value(&RoomMemberEventContent {
-        avatar_url: None,
-        blurhash: None,
-        displayname: None,
-        is_direct: None,
-        membership: MembershipState::Join,
-        third_party_invite: None,
-        reason: None,
-        join_authorized_via_users_server: None,
-    })
-    .expect("member event is valid value");
-
     let (_pdu, mut pdu_json) = services().rooms.timeline.create_hash_and_sign_event(
-        PduBuilder {
-            event_type: RoomEventType::RoomMember,
-            content,
-            unsigned: None,
-            state_key: Some(body.user_id.to_string()),
-            redacts: None,
-        },
+        services().rooms.state_cache.build_membership_event(
+            &body.room_id,
+            &body.user_id,
+            MembershipState::Join,
+            MembershipExtras::default(),
+        )?,
         &body.user_id,
         &body.room_id,
         &state_lock,
@@ -1607,6 +1607,8 @@ async fn create_join_event(
 pub async fn create_join_event_v1_route(
     body: Ruma<create_join_event::v1::Request>,
 ) -> Result<create_join_event::v1::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_servername = body
         .sender_servername
         .as_ref()
@@ -1623,6 +1625,8 @@ pub async fn create_join_event_v1_route(
 pub async fn create_join_event_v2_route(
     body: Ruma<create_join_event::v2::Request>,
 ) -> Result<create_join_event::v2::Response> {
+    services().globals.ensure_writable()?;
+
     let sender_servername = body
         .sender_servername
         .as_ref()
@@ -1633,6 +1637,293 @@ pub async fn create_join_event_v2_route(
     Ok(create_join_event::v2::Response { room_state })
 }
 
+/// Returns whether the join rule of a room allows knocking in the given room version.
+fn knock_allowed(join_rule: &JoinRule, room_version: &RoomVersionId) -> bool {
+    let supports_knock = !matches!(
+        room_version,
+        RoomVersionId::V1
+            | RoomVersionId::V2
+            | RoomVersionId::V3
+            | RoomVersionId::V4
+            | RoomVersionId::V5
+            | RoomVersionId::V6
+    );
+    let supports_knock_restricted = supports_knock
+        && !matches!(
+            room_version,
+            RoomVersionId::V7 | RoomVersionId::V8 | RoomVersionId::V9
+        );
+
+    match join_rule {
+        JoinRule::Knock => supports_knock,
+        JoinRule::KnockRestricted { .. } => supports_knock_restricted,
+        _ => false,
+    }
+}
+
+/// Makes sure the join rules of the room allow knocking.
+fn check_knock_allowed(room_id: &RoomId, room_version_id: &RoomVersionId) -> Result<()> {
+    let join_rules_event = services().rooms.state_accessor.room_state_get(
+        room_id,
+        &StateEventType::RoomJoinRules,
+        "",
+    )?;
+
+    let join_rule = join_rules_event
+        .as_ref()
+        .map(|join_rules_event| {
+            serde_json::from_str::<RoomJoinRulesEventContent>(join_rules_event.content.get())
+                .map_err(|e| {
+                    warn!("Invalid join rules event: {}", e);
+                    Error::bad_database("Invalid join rules event in db.")
+                })
+        })
+        .transpose()?
+        .map(|content| content.join_rule);
+
+    match join_rule {
+        Some(join_rule) if knock_allowed(&join_rule, room_version_id) => Ok(()),
+        _ => Err(Error::BadRequest(
+            ErrorKind::Forbidden,
+            "This room does not allow knocking.",
+        )),
+    }
+}
+
+/// Creates a knock event template for a remote user.
+async fn make_knock(
+    room_id: &RoomId,
+    user_id: &UserId,
+    ver: &[RoomVersionId],
+) -> Result<(RoomVersionId, CanonicalJsonObject)> {
+    if !services().rooms.metadata.exists(room_id)? {
+        return Err(Error::BadRequest(
+            ErrorKind::NotFound,
+            "Room is unknown to this server.",
+        ));
+    }
+
+    let room_version_id = services().rooms.state.get_room_version(room_id)?;
+    if !ver.contains(&room_version_id) {
+        return Err(Error::BadRequest(
+            ErrorKind::IncompatibleRoomVersion {
+                room_version: room_version_id,
+            },
+            "Room version not supported.",
+        ));
+    }
+
+    check_knock_allowed(room_id, &room_version_id)?;
+
+    let mutex_state = Arc::clone(
+        services()
+            .globals
+            .roomid_mutex_state
+            .write()
+            .unwrap()
+            .entry(room_id.to_owned())
+            .or_default(),
+    );
+    let state_lock = mutex_state.lock().await;
+
+    let (_pdu, mut pdu_json) = services().rooms.timeline.create_hash_and_sign_event(
+        services().rooms.state_cache.build_membership_event(
+            room_id,
+            user_id,
+            MembershipState::Knock,
+            MembershipExtras::default(),
+        )?,
+        user_id,
+        room_id,
+        &state_lock,
+    )?;
+
+    drop(state_lock);
+
+    pdu_json.remove("event_id");
+
+    Ok((room_version_id, pdu_json))
+}
+
+/// Validates and persists a knock event from a remote server and returns the stripped state the
+/// knocking user gets to see.
+async fn send_knock(
+    sender_servername: &ServerName,
+    room_id: &RoomId,
+    pdu: &RawJsonValue,
+) -> Result<Vec<Raw<AnyStrippedStateEvent>>> {
+    if !services().rooms.metadata.exists(room_id)? {
+        return Err(Error::BadRequest(
+            ErrorKind::NotFound,
+            "Room is unknown to this server.",
+        ));
+    }
+
+    let room_version_id = services().rooms.state.get_room_version(room_id)?;
+    check_knock_allowed(room_id, &room_version_id)?;
+
+    // We do not add the event_id field to the pdu here because of signature and hashes checks
+    let (event_id, value) = match gen_event_id_canonical_json(pdu, &room_version_id) {
+        Ok(t) => t,
+        Err(_) => {
+            // Event could not be converted to canonical json
+            return Err(Error::BadRequest(
+                ErrorKind::InvalidParam,
+                "Could not convert event to canonical json.",
+            ));
+        }
+    };
+
+    let sender: OwnedUserId = serde_json::from_value(
+        value
+            .get("sender")
+            .ok_or(Error::BadRequest(
+                ErrorKind::InvalidParam,
+                "Event had no sender field.",
+            ))?
+            .clone()
+            .into(),
+    )
+    .map_err(|_| Error::BadRequest(ErrorKind::InvalidParam, "sender is not a user id."))?;
+
+    if sender.server_name() != sender_servername {
+        return Err(Error::BadRequest(
+            ErrorKind::Forbidden,
+            "Knock event was not sent by the knocking server.",
+        ));
+    }
+
+    if value.get("state_key").and_then(|s| s.as_str()) != Some(sender.as_str()) {
+        return Err(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "Knock event state_key does not match the sender.",
+        ));
+    }
+
+    let membership = value
+        .get("content")
+        .and_then(|content| content.as_object())
+        .and_then(|content| content.get("membership"))
+        .and_then(|membership| membership.as_str());
+    if value.get("type").and_then(|t| t.as_str()) != Some("m.room.member")
+        || membership != Some("knock")
+    {
+        return Err(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "Event is not a knock membership event.",
+        ));
+    }
+
+    let pub_key_map = RwLock::new(BTreeMap::new());
+
+    let mutex = Arc::clone(
+        services()
+            .globals
+            .roomid_mutex_federation
+            .write()
+            .unwrap()
+            .entry(room_id.to_owned())
+            .or_default(),
+    );
+    let mutex_lock = mutex.lock().await;
+    let pdu_id: Vec<u8> = services()
+        .rooms
+        .event_handler
+        .handle_incoming_pdu(
+            sender_servername,
+            &event_id,
+            room_id,
+            value,
+            true,
+            &pub_key_map,
+        )
+        .await?
+        .ok_or(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "Could not accept incoming PDU as timeline event.",
+        ))?;
+    drop(mutex_lock);
+
+    let servers = services()
+        .rooms
+        .state_cache
+        .room_servers(room_id)
+        .filter_map(|r| r.ok())
+        .filter(|server| &**server != services().globals.server_name());
+
+    services().sending.send_pdu(servers, &pdu_id)?;
+
+    let knock_event = services()
+        .rooms
+        .timeline
+        .get_pdu_from_id(&pdu_id)?
+        .ok_or_else(|| Error::bad_database("Knock event was just added but is missing."))?;
+
+    services().rooms.state.calculate_invite_state(&knock_event)
+}
+
+/// # `GET /_matrix/federation/v1/make_knock/{roomId}/{userId}`
+///
+/// Creates a knock template.
+pub async fn create_knock_event_template_route(
+    body: Ruma<create_knock_event_template::v1::Request>,
+) -> Result<create_knock_event_template::v1::Response> {
+    if !services().globals.allow_federation() {
+        return Err(Error::bad_config("Federation is disabled."));
+    }
+
+    let sender_servername = body
+        .sender_servername
+        .as_ref()
+        .expect("server is authenticated");
+
+    services()
+        .rooms
+        .event_handler
+        .acl_check(sender_servername, &body.room_id)?;
+
+    if body.user_id.server_name() != sender_servername {
+        return Err(Error::BadRequest(
+            ErrorKind::Forbidden,
+            "User does not belong to the requesting server.",
+        ));
+    }
+
+    let (room_version, event) = make_knock(&body.room_id, &body.user_id, &body.ver).await?;
+
+    Ok(create_knock_event_template::v1::Response {
+        room_version,
+        event: to_raw_value(&event).expect("CanonicalJson can be serialized to JSON"),
+    })
+}
+
+/// # `PUT /_matrix/federation/v1/send_knock/{roomId}/{eventId}`
+///
+/// Submits a signed knock event.
+pub async fn create_knock_event_route(
+    body: Ruma<send_knock::v1::Request>,
+) -> Result<send_knock::v1::Response> {
+    if !services().globals.allow_federation() {
+        return Err(Error::bad_config("Federation is disabled."));
+    }
+
+    services().globals.ensure_writable()?;
+
+    let sender_servername = body
+        .sender_servername
+        .as_ref()
+        .expect("server is authenticated");
+
+    services()
+        .rooms
+        .event_handler
+        .acl_check(sender_servername, &body.room_id)?;
+
+    let knock_room_state = send_knock(sender_servername, &body.room_id, &body.pdu).await?;
+
+    Ok(send_knock::v1::Response { knock_room_state })
+}
+
 /// # `PUT /_matrix/federation/v2/invite/{roomId}/{eventId}`
 ///
 /// Invites a remote user to a room.
@@ -1643,6 +1934,8 @@ pub async fn create_invite_route(
         return Err(Error::bad_config("Federation is disabled."));
     }
 
+    services().globals.ensure_writable()?;
+
     let sender_servername = body
         .sender_servername
         .as_ref()
@@ -1741,6 +2034,7 @@ pub async fn create_invite_route(
             MembershipState::Invite,
             &sender,
             Some(invite_state),
+            None,
             true,
         )?;
     }
@@ -1899,7 +2193,17 @@ pub async fn claim_keys_route(
 
 #[cfg(test)]
 mod tests {
-    use super::{add_port_to_hostname, get_ip_with_port, FedDest};
+    use ruma::{
+        api::client::{
+            error::ErrorKind,
+            room::create_room::{self, v3::RoomPreset},
+        },
+        events::room::join_rules::JoinRule,
+        user_id, RoomVersionId,
+    };
+
+    use super::{add_port_to_hostname, get_ip_with_port, knock_allowed, make_knock, FedDest};
+    use crate::{api::client_server::create_room_route, services, testing, Error};
 
     #[test]
     fn ips_get_default_ports() {
@@ -1940,4 +2244,32 @@ mod tests {
             FedDest::Named(String::from("example.com"), String::from(":1337"))
         )
     }
+
+    #[test]
+    fn knocking_requires_a_knockable_room() {
+        assert!(knock_allowed(&JoinRule::Knock, &RoomVersionId::V9));
+        assert!(!knock_allowed(&JoinRule::Knock, &RoomVersionId::V6));
+        assert!(!knock_allowed(&JoinRule::Invite, &RoomVersionId::V9));
+        assert!(!knock_allowed(&JoinRule::Public, &RoomVersionId::V9));
+    }
+
+    #[tokio::test]
+    async fn knock_on_invite_only_room_is_forbidden() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let mut body = create_room::v3::Request::new();
+        body.preset = Some(RoomPreset::PrivateChat);
+        let room_id = create_room_route(testing::request(&alice, body))
+            .await
+            .unwrap()
+            .room_id;
+        let room_version = services().rooms.state.get_room_version(&room_id).unwrap();
+
+        let result = make_knock(&room_id, user_id!("@knocker:remote.test"), &[room_version]).await;
+
+        assert!(matches!(
+            result,
+            Err(Error::BadRequest(ErrorKind::Forbidden, _))
+        ));
+    }
 }
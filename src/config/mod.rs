@@ -28,6 +28,8 @@ pub struct Config {
     pub db_cache_capacity_mb: f64,
     #[serde(default = "true_fn")]
     pub enable_lightning_bolt: bool,
+    #[serde(default = "false_fn")]
+    pub startup_self_check: bool,
     #[serde(default = "default_conduit_cache_capacity_modifier")]
     pub conduit_cache_capacity_modifier: f64,
     #[serde(default = "default_rocksdb_max_open_files")]
@@ -42,6 +44,20 @@ pub struct Config {
     pub max_concurrent_requests: u16,
     #[serde(default = "default_max_fetch_prev_events")]
     pub max_fetch_prev_events: u16,
+    #[serde(default = "default_max_fetch_keys_servers")]
+    pub max_fetch_keys_servers: u16,
+    pub sender_workers: Option<u16>,
+    #[serde(default = "default_max_aliases_per_room")]
+    pub max_aliases_per_room: u32,
+    #[serde(default = "default_min_typing_timeout_s")]
+    pub min_typing_timeout_s: u64,
+    #[serde(default = "default_max_typing_timeout_s")]
+    pub max_typing_timeout_s: u64,
+    #[serde(default = "false_fn")]
+    pub maintenance_mode: bool,
+    #[serde(default = "true_fn")]
+    pub presence_enabled: bool,
+    pub max_initial_sync_rooms: Option<u32>,
     #[serde(default = "false_fn")]
     pub allow_registration: bool,
     #[serde(default = "true_fn")]
@@ -128,6 +144,7 @@ impl fmt::Display for Config {
                 "Maximum open files for RocksDB",
                 &self.rocksdb_max_open_files.to_string(),
             ),
+            ("Startup self-check", &self.startup_self_check.to_string()),
             ("PDU cache capacity", &self.pdu_cache_capacity.to_string()),
             (
                 "Cleanup interval in seconds",
@@ -138,6 +155,24 @@ impl fmt::Display for Config {
                 "Maximum concurrent requests",
                 &self.max_concurrent_requests.to_string(),
             ),
+            (
+                "Maximum servers to fetch keys from at once",
+                &self.max_fetch_keys_servers.to_string(),
+            ),
+            (
+                "Federation sender workers",
+                &self
+                    .sender_workers
+                    .map_or_else(|| "unlimited".to_owned(), |workers| workers.to_string()),
+            ),
+            ("Maintenance mode", &self.maintenance_mode.to_string()),
+            ("Presence enabled", &self.presence_enabled.to_string()),
+            (
+                "Maximum rooms in initial sync",
+                &self
+                    .max_initial_sync_rooms
+                    .map_or_else(|| "unlimited".to_owned(), |limit| limit.to_string()),
+            ),
             ("Allow registration", &self.allow_registration.to_string()),
             (
                 "Enabled lightning bolt",
@@ -255,6 +290,22 @@ fn default_max_fetch_prev_events() -> u16 {
     100_u16
 }
 
+fn default_max_fetch_keys_servers() -> u16 {
+    16
+}
+
+fn default_max_aliases_per_room() -> u32 {
+    100
+}
+
+fn default_min_typing_timeout_s() -> u64 {
+    1
+}
+
+fn default_max_typing_timeout_s() -> u64 {
+    30
+}
+
 fn default_log() -> String {
     "warn,state_res=warn,_=off,sled=off".to_owned()
 }
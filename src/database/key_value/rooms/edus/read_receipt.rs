@@ -147,4 +147,25 @@ impl service::rooms::edus::read_receipt::Data for KeyValueDatabase {
             .transpose()?
             .unwrap_or(0))
     }
+
+    fn purge_room(&self, room_id: &RoomId) -> Result<()> {
+        let mut prefix = room_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        for tree in [
+            &self.readreceiptid_readreceipt,
+            &self.roomuserid_privateread,
+            &self.roomuserid_lastprivatereadupdate,
+        ] {
+            let keys: Vec<_> = tree
+                .scan_prefix(prefix.clone())
+                .map(|(key, _)| key)
+                .collect();
+            for key in keys {
+                tree.remove(&key)?;
+            }
+        }
+
+        Ok(())
+    }
 }
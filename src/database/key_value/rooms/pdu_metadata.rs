@@ -15,6 +15,16 @@ impl service::rooms::pdu_metadata::Data for KeyValueDatabase {
         Ok(())
     }
 
+    fn unmark_as_referenced(&self, room_id: &RoomId, event_ids: &[Arc<EventId>]) -> Result<()> {
+        for event_id in event_ids {
+            let mut key = room_id.as_bytes().to_vec();
+            key.extend_from_slice(event_id.as_bytes());
+            self.referencedevents.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
     fn is_event_referenced(&self, room_id: &RoomId, event_id: &EventId) -> Result<bool> {
         let mut key = room_id.as_bytes().to_vec();
         key.extend_from_slice(event_id.as_bytes());
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use ruma::RoomId;
 
 use crate::{database::KeyValueDatabase, service, services, utils, Result};
@@ -20,6 +22,43 @@ impl service::rooms::search::Data for KeyValueDatabase {
         self.tokenids.insert_batch(&mut batch)
     }
 
+    fn clear_room_index(&self, shortroomid: u64) -> Result<()> {
+        let keys: Vec<_> = self
+            .tokenids
+            .scan_prefix(shortroomid.to_be_bytes().to_vec())
+            .map(|(key, _)| key)
+            .collect();
+
+        for key in keys {
+            self.tokenids.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn retain_room_index(&self, shortroomid: u64, pdu_ids: &HashSet<Vec<u8>>) -> Result<()> {
+        let prefix = shortroomid.to_be_bytes().to_vec();
+
+        let stale: Vec<_> = self
+            .tokenids
+            .scan_prefix(prefix.clone())
+            .map(|(key, _)| key)
+            .filter(|key| {
+                // Words never contain 0xff, so the pdu id starts after the first one
+                key[prefix.len()..]
+                    .splitn(2, |&b| b == 0xff)
+                    .nth(1)
+                    .map_or(true, |pdu_id| !pdu_ids.contains(pdu_id))
+            })
+            .collect();
+
+        for key in stale {
+            self.tokenids.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
     fn search_pdus<'a>(
         &'a self,
         room_id: &RoomId,
@@ -31,6 +31,39 @@ impl service::rooms::short::Data for KeyValueDatabase {
         Ok(short)
     }
 
+    fn get_shorteventid(&self, event_id: &EventId) -> Result<Option<u64>> {
+        self.eventid_shorteventid
+            .get(event_id.as_bytes())?
+            .map(|shorteventid| {
+                utils::u64_from_bytes(&shorteventid)
+                    .map_err(|_| Error::bad_database("Invalid shorteventid in db."))
+            })
+            .transpose()
+    }
+
+    fn remove_shorteventid(&self, event_id: &EventId, shorteventid: u64) -> Result<()> {
+        self.eventid_shorteventid.remove(event_id.as_bytes())?;
+        self.shorteventid_eventid
+            .remove(&shorteventid.to_be_bytes())?;
+
+        self.eventidshort_cache.lock().unwrap().remove(event_id);
+        self.shorteventid_cache
+            .lock()
+            .unwrap()
+            .remove(&shorteventid);
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn set_shorteventid(&self, event_id: &EventId, shorteventid: u64) -> Result<()> {
+        self.eventid_shorteventid
+            .insert(event_id.as_bytes(), &shorteventid.to_be_bytes())?;
+        self.eventidshort_cache.lock().unwrap().remove(event_id);
+
+        Ok(())
+    }
+
     fn get_shortstatekey(
         &self,
         event_type: &StateEventType,
@@ -215,4 +248,8 @@ impl service::rooms::short::Data for KeyValueDatabase {
             }
         })
     }
+
+    fn remove_shortroomid(&self, room_id: &RoomId) -> Result<()> {
+        self.roomid_shortroomid.remove(room_id.as_bytes())
+    }
 }
@@ -28,6 +28,29 @@ impl service::rooms::state::Data for KeyValueDatabase {
         Ok(())
     }
 
+    fn delete_room_state(
+        &self,
+        room_id: &RoomId,
+        shorteventids: &[u64],
+        _mutex_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
+    ) -> Result<()> {
+        self.roomid_shortstatehash.remove(room_id.as_bytes())?;
+
+        for shorteventid in shorteventids {
+            self.shorteventid_shortstatehash
+                .remove(&shorteventid.to_be_bytes())?;
+        }
+
+        let mut prefix = room_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        for (key, _) in self.roomid_pduleaves.scan_prefix(prefix) {
+            self.roomid_pduleaves.remove(&key)?;
+        }
+
+        Ok(())
+    }
+
     fn set_event_state(&self, shorteventid: u64, shortstatehash: u64) -> Result<()> {
         self.shorteventid_shortstatehash
             .insert(&shorteventid.to_be_bytes(), &shortstatehash.to_be_bytes())?;
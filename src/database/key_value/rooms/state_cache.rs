@@ -7,7 +7,11 @@ use ruma::{
     OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
 };
 
-use crate::{database::KeyValueDatabase, service, services, utils, Error, Result};
+use crate::{
+    database::KeyValueDatabase,
+    service::{self, rooms::state_cache::MembershipOrdering},
+    services, utils, Error, Result,
+};
 
 impl service::rooms::state_cache::Data for KeyValueDatabase {
     fn mark_as_once_joined(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
@@ -36,6 +40,28 @@ impl service::rooms::state_cache::Data for KeyValueDatabase {
         Ok(())
     }
 
+    fn purge_room(&self, room_id: &RoomId, user_ids: &[OwnedUserId]) -> Result<()> {
+        for user_id in user_ids {
+            let mut userroom_id = user_id.as_bytes().to_vec();
+            userroom_id.push(0xff);
+            userroom_id.extend_from_slice(room_id.as_bytes());
+
+            let mut roomuser_id = room_id.as_bytes().to_vec();
+            roomuser_id.push(0xff);
+            roomuser_id.extend_from_slice(user_id.as_bytes());
+
+            self.roomuseroncejoinedids.remove(&userroom_id)?;
+            self.userroomid_leftstate.remove(&userroom_id)?;
+            self.roomuserid_leftcount.remove(&roomuser_id)?;
+            self.roomuserid_membershipordering.remove(&roomuser_id)?;
+        }
+
+        self.roomid_joinedcount.remove(room_id.as_bytes())?;
+        self.roomid_invitedcount.remove(room_id.as_bytes())?;
+
+        Ok(())
+    }
+
     fn mark_as_invited(
         &self,
         user_id: &UserId,
@@ -92,6 +118,50 @@ impl service::rooms::state_cache::Data for KeyValueDatabase {
         Ok(())
     }
 
+    fn get_membership_ordering(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MembershipOrdering>> {
+        let mut roomuser_id = room_id.as_bytes().to_vec();
+        roomuser_id.push(0xff);
+        roomuser_id.extend_from_slice(user_id.as_bytes());
+
+        self.roomuserid_membershipordering
+            .get(&roomuser_id)?
+            .map(|bytes| {
+                let invalid =
+                    || Error::bad_database("Invalid ordering in roomuserid_membershipordering.");
+
+                if bytes.len() != 16 {
+                    return Err(invalid());
+                }
+
+                Ok(MembershipOrdering {
+                    depth: utils::u64_from_bytes(&bytes[..8]).map_err(|_| invalid())?,
+                    origin_server_ts: utils::u64_from_bytes(&bytes[8..]).map_err(|_| invalid())?,
+                })
+            })
+            .transpose()
+    }
+
+    fn set_membership_ordering(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+        ordering: MembershipOrdering,
+    ) -> Result<()> {
+        let mut roomuser_id = room_id.as_bytes().to_vec();
+        roomuser_id.push(0xff);
+        roomuser_id.extend_from_slice(user_id.as_bytes());
+
+        let mut value = ordering.depth.to_be_bytes().to_vec();
+        value.extend_from_slice(&ordering.origin_server_ts.to_be_bytes());
+
+        self.roomuserid_membershipordering
+            .insert(&roomuser_id, &value)
+    }
+
     fn update_joined_count(&self, room_id: &RoomId) -> Result<()> {
         let mut joinedcount = 0_u64;
         let mut invitedcount = 0_u64;
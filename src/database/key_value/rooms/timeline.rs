@@ -1,7 +1,14 @@
-use std::{collections::hash_map, mem::size_of, sync::Arc};
+use std::{
+    collections::hash_map,
+    mem::size_of,
+    sync::{Arc, Mutex},
+};
+
+use lru_cache::LruCache;
 
 use ruma::{
-    api::client::error::ErrorKind, CanonicalJsonObject, EventId, OwnedUserId, RoomId, UserId,
+    api::client::error::ErrorKind, CanonicalJsonObject, EventId, OwnedEventId, OwnedUserId, RoomId,
+    UserId,
 };
 use tracing::error;
 
@@ -105,13 +112,8 @@ impl service::rooms::timeline::Data for KeyValueDatabase {
     ///
     /// Checks the `eventid_outlierpdu` Tree if not found in the timeline.
     fn get_pdu(&self, event_id: &EventId) -> Result<Option<Arc<PduEvent>>> {
-        if let Some(p) = self.pdu_cache.lock().unwrap().get_mut(event_id) {
-            return Ok(Some(Arc::clone(p)));
-        }
-
-        if let Some(pdu) = self
-            .get_non_outlier_pdu(event_id)?
-            .map_or_else(
+        get_cached(&self.pdu_cache, event_id, || {
+            self.get_non_outlier_pdu(event_id)?.map_or_else(
                 || {
                     self.eventid_outlierpdu
                         .get(event_id.as_bytes())?
@@ -122,17 +124,8 @@ impl service::rooms::timeline::Data for KeyValueDatabase {
                         .transpose()
                 },
                 |x| Ok(Some(x)),
-            )?
-            .map(Arc::new)
-        {
-            self.pdu_cache
-                .lock()
-                .unwrap()
-                .insert(event_id.to_owned(), Arc::clone(&pdu));
-            Ok(Some(pdu))
-        } else {
-            Ok(None)
-        }
+            )
+        })
     }
 
     /// Returns the pdu.
@@ -213,6 +206,19 @@ impl service::rooms::timeline::Data for KeyValueDatabase {
         }
     }
 
+    fn remove_pdu(&self, room_id: &RoomId, event_id: &EventId) -> Result<()> {
+        if let Some(pdu_id) = self.eventid_pduid.get(event_id.as_bytes())? {
+            self.pduid_pdu.remove(&pdu_id)?;
+        }
+        self.eventid_pduid.remove(event_id.as_bytes())?;
+        self.eventid_outlierpdu.remove(event_id.as_bytes())?;
+
+        self.pdu_cache.lock().unwrap().remove(event_id);
+        self.lasttimelinecount_cache.lock().unwrap().remove(room_id);
+
+        Ok(())
+    }
+
     /// Returns an iterator over all events and their tokens in a room that happened before the
     /// event with id `until` in reverse-chronological order.
     fn pdus_until<'a>(
@@ -352,3 +358,51 @@ fn count_to_id(
 
     Ok((prefix, pdu_id))
 }
+
+/// Returns the cached value for this event or loads, caches and returns it.
+fn get_cached<V>(
+    cache: &Mutex<LruCache<OwnedEventId, Arc<V>>>,
+    event_id: &EventId,
+    load: impl FnOnce() -> Result<Option<V>>,
+) -> Result<Option<Arc<V>>> {
+    if let Some(value) = cache.lock().unwrap().get_mut(event_id) {
+        return Ok(Some(Arc::clone(value)));
+    }
+
+    Ok(load()?.map(|value| {
+        let value = Arc::new(value);
+        cache
+            .lock()
+            .unwrap()
+            .insert(event_id.to_owned(), Arc::clone(&value));
+        value
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use lru_cache::LruCache;
+    use ruma::event_id;
+
+    use super::get_cached;
+
+    #[test]
+    fn repeated_get_pdu_hits_the_cache() {
+        let cache = Mutex::new(LruCache::new(10));
+        let event_id = event_id!("$event");
+        let mut loads = 0;
+
+        for _ in 0..3 {
+            let pdu = get_cached(&cache, event_id, || {
+                loads += 1;
+                Ok(Some("pdu".to_owned()))
+            })
+            .unwrap();
+            assert_eq!(pdu.as_deref().map(String::as_str), Some("pdu"));
+        }
+
+        assert_eq!(loads, 1);
+    }
+}
@@ -1,4 +1,4 @@
-use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use ruma::{DeviceId, OwnedRoomId, OwnedUserId, RoomId, UserId};
 
 use crate::{database::KeyValueDatabase, service, services, utils, Error, Result};
 
@@ -69,6 +69,140 @@ impl service::rooms::user::Data for KeyValueDatabase {
             .unwrap_or(0))
     }
 
+    fn set_room_last_update(&self, user_id: &UserId, room_id: &RoomId, count: u64) -> Result<()> {
+        let mut userroom_id = user_id.as_bytes().to_vec();
+        userroom_id.push(0xff);
+        userroom_id.extend_from_slice(room_id.as_bytes());
+
+        self.userroomid_lastupdate
+            .insert(&userroom_id, &count.to_be_bytes())
+    }
+
+    fn remove_room_last_update(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
+        let mut userroom_id = user_id.as_bytes().to_vec();
+        userroom_id.push(0xff);
+        userroom_id.extend_from_slice(room_id.as_bytes());
+
+        self.userroomid_lastupdate.remove(&userroom_id)
+    }
+
+    fn purge_room(&self, room_id: &RoomId, user_ids: &[OwnedUserId]) -> Result<()> {
+        for user_id in user_ids {
+            let mut userroom_id = user_id.as_bytes().to_vec();
+            userroom_id.push(0xff);
+            userroom_id.extend_from_slice(room_id.as_bytes());
+
+            let mut roomuser_id = room_id.as_bytes().to_vec();
+            roomuser_id.push(0xff);
+            roomuser_id.extend_from_slice(user_id.as_bytes());
+
+            self.userroomid_notificationcount.remove(&userroom_id)?;
+            self.userroomid_highlightcount.remove(&userroom_id)?;
+            self.userroomid_lastupdate.remove(&userroom_id)?;
+            self.roomuserid_lastnotificationread.remove(&roomuser_id)?;
+
+            let mut prefix = user_id.as_bytes().to_vec();
+            prefix.push(0xff);
+            for (key, _) in self.userdeviceroomid_omitted.scan_prefix(prefix) {
+                if key.rsplit(|&b| b == 0xff).next() == Some(room_id.as_bytes()) {
+                    self.userdeviceroomid_omitted.remove(&key)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rooms_last_update<'a>(
+        &'a self,
+        user_id: &UserId,
+    ) -> Box<dyn Iterator<Item = Result<(OwnedRoomId, u64)>> + 'a> {
+        let mut prefix = user_id.as_bytes().to_vec();
+        prefix.push(0xff);
+
+        Box::new(
+            self.userroomid_lastupdate
+                .scan_prefix(prefix)
+                .map(|(key, value)| {
+                    let room_id = RoomId::parse(
+                        utils::string_from_bytes(
+                            key.rsplit(|&b| b == 0xff)
+                                .next()
+                                .expect("rsplit always returns an element"),
+                        )
+                        .map_err(|_| {
+                            Error::bad_database(
+                                "Room ID in userroomid_lastupdate is invalid unicode.",
+                            )
+                        })?,
+                    )
+                    .map_err(|_| {
+                        Error::bad_database("Room ID in userroomid_lastupdate is invalid.")
+                    })?;
+
+                    let count = utils::u64_from_bytes(&value).map_err(|_| {
+                        Error::bad_database("Invalid count in userroomid_lastupdate.")
+                    })?;
+
+                    Ok((room_id, count))
+                }),
+        )
+    }
+
+    fn set_initial_sync_omitted(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_ids: &[OwnedRoomId],
+    ) -> Result<()> {
+        let mut prefix = user_id.as_bytes().to_vec();
+        prefix.push(0xff);
+        prefix.extend_from_slice(device_id.as_bytes());
+        prefix.push(0xff);
+
+        for (key, _) in self.userdeviceroomid_omitted.scan_prefix(prefix.clone()) {
+            self.userdeviceroomid_omitted.remove(&key)?;
+        }
+
+        for room_id in room_ids {
+            let mut key = prefix.clone();
+            key.extend_from_slice(room_id.as_bytes());
+            self.userdeviceroomid_omitted.insert(&key, &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn was_omitted_from_initial_sync(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_id: &RoomId,
+    ) -> Result<bool> {
+        let mut key = user_id.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(device_id.as_bytes());
+        key.push(0xff);
+        key.extend_from_slice(room_id.as_bytes());
+
+        Ok(self.userdeviceroomid_omitted.get(&key)?.is_some())
+    }
+
+    fn remove_initial_sync_omitted(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_id: &RoomId,
+    ) -> Result<()> {
+        let mut key = user_id.as_bytes().to_vec();
+        key.push(0xff);
+        key.extend_from_slice(device_id.as_bytes());
+        key.push(0xff);
+        key.extend_from_slice(room_id.as_bytes());
+
+        self.userdeviceroomid_omitted.remove(&key)
+    }
+
     fn associate_token_shortstatehash(
         &self,
         room_id: &RoomId,
@@ -95,6 +95,7 @@ pub struct KeyValueDatabase {
     pub(super) roomuserid_invitecount: Arc<dyn KvTree>, // InviteCount = Count
     pub(super) userroomid_leftstate: Arc<dyn KvTree>,
     pub(super) roomuserid_leftcount: Arc<dyn KvTree>,
+    pub(super) roomuserid_membershipordering: Arc<dyn KvTree>, // MembershipOrdering = Depth + OriginServerTs
 
     pub(super) disabledroomids: Arc<dyn KvTree>, // Rooms where incoming federation handling is disabled
 
@@ -103,6 +104,8 @@ pub struct KeyValueDatabase {
     pub(super) userroomid_notificationcount: Arc<dyn KvTree>, // NotifyCount = u64
     pub(super) userroomid_highlightcount: Arc<dyn KvTree>,    // HightlightCount = u64
     pub(super) roomuserid_lastnotificationread: Arc<dyn KvTree>, // LastNotificationRead = u64
+    pub(super) userroomid_lastupdate: Arc<dyn KvTree>,        // LastUpdate = Count
+    pub(super) userdeviceroomid_omitted: Arc<dyn KvTree>, // Rooms left out of the initial sync of a device
 
     /// Remember the current state hash of a room.
     pub(super) roomid_shortstatehash: Arc<dyn KvTree>,
@@ -315,6 +318,7 @@ impl KeyValueDatabase {
             roomuserid_invitecount: builder.open_tree("roomuserid_invitecount")?,
             userroomid_leftstate: builder.open_tree("userroomid_leftstate")?,
             roomuserid_leftcount: builder.open_tree("roomuserid_leftcount")?,
+            roomuserid_membershipordering: builder.open_tree("roomuserid_membershipordering")?,
 
             disabledroomids: builder.open_tree("disabledroomids")?,
 
@@ -323,6 +327,8 @@ impl KeyValueDatabase {
             userroomid_notificationcount: builder.open_tree("userroomid_notificationcount")?,
             userroomid_highlightcount: builder.open_tree("userroomid_highlightcount")?,
             roomuserid_lastnotificationread: builder.open_tree("userroomid_highlightcount")?,
+            userroomid_lastupdate: builder.open_tree("userroomid_lastupdate")?,
+            userdeviceroomid_omitted: builder.open_tree("userdeviceroomid_omitted")?,
 
             statekey_shortstatekey: builder.open_tree("statekey_shortstatekey")?,
             shortstatekey_statekey: builder.open_tree("shortstatekey_statekey")?,
@@ -411,7 +417,7 @@ impl KeyValueDatabase {
         }
 
         // If the database has any data, perform data migrations before starting
-        let latest_database_version = 12;
+        let latest_database_version = 13;
 
         if services().users.count()? > 0 {
             // MIGRATIONS
@@ -880,6 +886,34 @@ impl KeyValueDatabase {
                 warn!("Migration: 11 -> 12 finished");
             }
 
+            if services().globals.database_version()? < 13 {
+                // Sync only loads rooms that changed for the user, so record the last event of
+                // every room they are in
+                for user_id in services().users.iter().filter_map(|r| r.ok()) {
+                    for room_id in services()
+                        .rooms
+                        .state_cache
+                        .rooms_joined(&user_id)
+                        .filter_map(|r| r.ok())
+                    {
+                        if let PduCount::Normal(count) = services()
+                            .rooms
+                            .timeline
+                            .last_timeline_count(&user_id, &room_id)?
+                        {
+                            services()
+                                .rooms
+                                .user
+                                .mark_room_updated(&user_id, &room_id, count)?;
+                        }
+                    }
+                }
+
+                services().globals.bump_database_version(13)?;
+
+                warn!("Migration: 12 -> 13 finished");
+            }
+
             assert_eq!(
                 services().globals.database_version().unwrap(),
                 latest_database_version
@@ -905,6 +939,17 @@ impl KeyValueDatabase {
             );
         }
 
+        if services().globals.startup_self_check() {
+            let violations = services().rooms.self_check()?;
+            for violation in &violations {
+                error!("Database invariant violated: {}", violation);
+            }
+            info!(
+                "Database self-check finished with {} violations",
+                violations.len()
+            );
+        }
+
         // This data is probably outdated
         db.presenceid_presence.clear()?;
 
@@ -11,6 +11,8 @@ pub mod api;
 mod config;
 mod database;
 mod service;
+#[cfg(test)]
+mod testing;
 mod utils;
 
 use std::sync::RwLock;
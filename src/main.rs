@@ -404,6 +404,8 @@ fn routes() -> Router {
         .ruma_route(server_server::create_join_event_template_route)
         .ruma_route(server_server::create_join_event_v1_route)
         .ruma_route(server_server::create_join_event_v2_route)
+        .ruma_route(server_server::create_knock_event_template_route)
+        .ruma_route(server_server::create_knock_event_route)
         .ruma_route(server_server::create_invite_route)
         .ruma_route(server_server::get_devices_route)
         .ruma_route(server_server::get_room_information_route)
@@ -3,14 +3,14 @@ mod data;
 pub use data::Data;
 
 use ruma::{
-    events::{AnyEphemeralRoomEvent, RoomAccountDataEventType},
+    events::{AnyEphemeralRoomEvent, AnyGlobalAccountDataEvent, RoomAccountDataEventType},
     serde::Raw,
     RoomId, UserId,
 };
 
 use std::collections::HashMap;
 
-use crate::Result;
+use crate::{services, Result};
 
 pub struct Service {
     pub db: &'static dyn Data,
@@ -26,7 +26,17 @@ impl Service {
         event_type: RoomAccountDataEventType,
         data: &serde_json::Value,
     ) -> Result<()> {
-        self.db.update(room_id, user_id, event_type, data)
+        self.db.update(room_id, user_id, event_type, data)?;
+
+        if let Some(room_id) = room_id {
+            services().rooms.user.mark_room_updated(
+                user_id,
+                room_id,
+                services().globals.current_count()?,
+            )?;
+        }
+
+        Ok(())
     }
 
     /// Searches the account data for a specific kind.
@@ -50,4 +60,96 @@ impl Service {
     ) -> Result<HashMap<RoomAccountDataEventType, Raw<AnyEphemeralRoomEvent>>> {
         self.db.changes_since(room_id, user_id, since)
     }
+
+    /// Returns the global account data events that changed after `since`, for the top level
+    /// `account_data` section of sync.
+    ///
+    /// This includes `m.direct`, `m.ignored_user_list` and `m.push_rules`: the push rule endpoints
+    /// store the rules through `update`, which bumps the account data version.
+    #[tracing::instrument(skip(self, user_id, since))]
+    pub fn global_account_data_sync(
+        &self,
+        user_id: &UserId,
+        since: u64,
+    ) -> Result<Vec<Raw<AnyGlobalAccountDataEvent>>> {
+        Ok(self
+            .changes_since(None, user_id, since)?
+            .into_values()
+            .map(|event| event.cast())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::client::push::{set_pushrule_enabled, RuleKind, RuleScope},
+        events::{
+            push_rules::{PushRulesEvent, PushRulesEventContent},
+            AnyGlobalAccountDataEvent, GlobalAccountDataEventType,
+        },
+        push::Ruleset,
+    };
+
+    use crate::{api::client_server::set_pushrule_enabled_route, services, testing};
+
+    #[tokio::test]
+    async fn enabled_push_rule_is_synced() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+
+        let mut global = Ruleset::server_default(&alice);
+        let mut rule = global
+            .content
+            .get(".m.rule.contains_user_name")
+            .cloned()
+            .unwrap();
+        global.content.remove(&rule);
+        rule.enabled = false;
+        global.content.insert(rule);
+        services()
+            .account_data
+            .update(
+                None,
+                &alice,
+                GlobalAccountDataEventType::PushRules.to_string().into(),
+                &serde_json::to_value(PushRulesEvent {
+                    content: PushRulesEventContent { global },
+                })
+                .unwrap(),
+            )
+            .unwrap();
+        let since = services().globals.current_count().unwrap();
+
+        set_pushrule_enabled_route(testing::request(
+            &alice,
+            set_pushrule_enabled::v3::Request::new(
+                RuleScope::Global,
+                RuleKind::Content,
+                ".m.rule.contains_user_name".to_owned(),
+                true,
+            ),
+        ))
+        .await
+        .unwrap();
+
+        let synced = services()
+            .account_data
+            .global_account_data_sync(&alice, since)
+            .unwrap();
+        assert_eq!(synced.len(), 1);
+
+        match synced[0].deserialize().unwrap() {
+            AnyGlobalAccountDataEvent::PushRules(event) => assert!(
+                event
+                    .content
+                    .global
+                    .content
+                    .get(".m.rule.contains_user_name")
+                    .unwrap()
+                    .enabled
+            ),
+            _ => panic!("Expected a push rules event"),
+        }
+    }
 }
@@ -133,6 +133,16 @@ enum AdminCommand {
         event_id: Box<EventId>,
     },
 
+    /// Show the difference between the room states at two events
+    StateDiff {
+        /// The room both events belong to
+        room_id: Box<RoomId>,
+        /// The first event ID
+        event_a: Box<EventId>,
+        /// The second event ID
+        event_b: Box<EventId>,
+    },
+
     /// Print database memory usage statistics
     DatabaseMemoryUsage,
 
@@ -153,10 +163,22 @@ enum AdminCommand {
         password: Option<String>,
     },
 
+    /// Rebuild the search index for messages sent before indexing was available
+    ///
+    /// Reindexes all rooms if no room is given.
+    ReindexSearch { room_id: Option<Box<RoomId>> },
+
     /// Disables incoming federation handling for a room.
     DisableRoom { room_id: Box<RoomId> },
     /// Enables incoming federation handling for a room again.
     EnableRoom { room_id: Box<RoomId> },
+
+    /// Refuses writes from users and other servers until maintenance mode is disabled again.
+    ///
+    /// Admins can still use this room while the server is in maintenance mode.
+    EnableMaintenanceMode,
+    /// Accepts writes again.
+    DisableMaintenanceMode,
 }
 
 #[derive(Debug)]
@@ -531,6 +553,40 @@ impl Service {
                     None => RoomMessageEventContent::text_plain("PDU not found."),
                 }
             }
+            AdminCommand::StateDiff {
+                room_id,
+                event_a,
+                event_b,
+            } => {
+                let (only_in_a, only_in_b, changed) = services()
+                    .rooms
+                    .state_accessor
+                    .state_diff_between_events(&room_id, &event_a, &event_b)
+                    .await?;
+
+                let state_key = |pdu: &PduEvent| pdu.state_key.clone().unwrap_or_default();
+
+                let mut msg = format!("Only at {event_a}:\n");
+                for pdu in &only_in_a {
+                    msg += &format!("- {} \"{}\": {}\n", pdu.kind, state_key(pdu), pdu.event_id);
+                }
+                msg += &format!("\nOnly at {event_b}:\n");
+                for pdu in &only_in_b {
+                    msg += &format!("+ {} \"{}\": {}\n", pdu.kind, state_key(pdu), pdu.event_id);
+                }
+                msg += "\nChanged:\n";
+                for (a, b) in &changed {
+                    msg += &format!(
+                        "~ {} \"{}\": {} -> {}\n",
+                        a.kind,
+                        state_key(a),
+                        a.event_id,
+                        b.event_id
+                    );
+                }
+
+                RoomMessageEventContent::text_plain(msg)
+            }
             AdminCommand::DatabaseMemoryUsage => match services().globals.db.memory_usage() {
                 Ok(response) => RoomMessageEventContent::text_plain(response),
                 Err(e) => RoomMessageEventContent::text_plain(format!(
@@ -644,6 +700,20 @@ impl Service {
                     "Created user with user_id: {user_id} and password: {password}"
                 ))
             }
+            AdminCommand::ReindexSearch { room_id } => {
+                let start = Instant::now();
+                // Scanning every room takes a while, keep it off the async workers
+                let indexed = tokio::task::spawn_blocking(move || match room_id {
+                    Some(room_id) => services().rooms.search.reindex_room(&room_id),
+                    None => services().rooms.search.reindex_all(),
+                })
+                .await
+                .expect("reindexing does not panic")?;
+                let elapsed = start.elapsed();
+                RoomMessageEventContent::text_plain(format!(
+                    "Indexed {indexed} messages in {elapsed:?}"
+                ))
+            }
             AdminCommand::DisableRoom { room_id } => {
                 services().rooms.metadata.disable_room(&room_id, true)?;
                 RoomMessageEventContent::text_plain("Room disabled.")
@@ -652,6 +722,14 @@ impl Service {
                 services().rooms.metadata.disable_room(&room_id, false)?;
                 RoomMessageEventContent::text_plain("Room enabled.")
             }
+            AdminCommand::EnableMaintenanceMode => {
+                services().globals.set_maintenance_mode(true);
+                RoomMessageEventContent::text_plain("Maintenance mode enabled.")
+            }
+            AdminCommand::DisableMaintenanceMode => {
+                services().globals.set_maintenance_mode(false);
+                RoomMessageEventContent::text_plain("Maintenance mode disabled.")
+            }
             AdminCommand::DeactivateUser {
                 leave_rooms,
                 user_id,
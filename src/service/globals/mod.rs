@@ -9,7 +9,7 @@ use crate::api::server_server::FedDest;
 use crate::{services, Config, Error, Result};
 use ruma::{
     api::{
-        client::sync::sync_events,
+        client::{error::ErrorKind, sync::sync_events},
         federation::discovery::{ServerSigningKeys, VerifyKey},
     },
     DeviceId, RoomVersionId, ServerName, UserId,
@@ -61,6 +61,7 @@ pub struct Service {
     pub rotate: RotationHandler,
 
     pub shutdown: AtomicBool,
+    maintenance_mode: AtomicBool,
 }
 
 /// Handles "rotation" of long-polling requests. "Rotation" in this context is similar to "rotation" of log files and the like.
@@ -135,6 +136,8 @@ impl Service {
         // Experimental, partially supported room versions
         let unstable_room_versions = vec![RoomVersionId::V3, RoomVersionId::V4, RoomVersionId::V5];
 
+        let maintenance_mode = AtomicBool::new(config.maintenance_mode);
+
         let mut s = Self {
             db,
             config,
@@ -164,6 +167,7 @@ impl Service {
             sync_receivers: RwLock::new(HashMap::new()),
             rotate: RotationHandler::new(),
             shutdown: AtomicBool::new(false),
+            maintenance_mode,
         };
 
         fs::create_dir_all(s.get_media_folder())?;
@@ -230,6 +234,61 @@ impl Service {
         self.config.max_fetch_prev_events
     }
 
+    pub fn max_fetch_keys_servers(&self) -> u16 {
+        self.config.max_fetch_keys_servers
+    }
+
+    pub fn max_aliases_per_room(&self) -> u32 {
+        self.config.max_aliases_per_room
+    }
+
+    pub fn min_typing_timeout_s(&self) -> u64 {
+        self.config.min_typing_timeout_s
+    }
+
+    pub fn max_typing_timeout_s(&self) -> u64 {
+        self.config.max_typing_timeout_s
+    }
+
+    pub fn maintenance_mode(&self) -> bool {
+        self.maintenance_mode.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Overrides the configured maintenance mode until the next restart.
+    pub fn set_maintenance_mode(&self, enabled: bool) {
+        self.maintenance_mode
+            .store(enabled, atomic::Ordering::Relaxed);
+    }
+
+    /// Refuses write requests while the server is in maintenance mode. Reads and sync keep
+    /// working.
+    ///
+    /// Login, logout and filter creation stay allowed, because clients need them to sign in and
+    /// sync. Refused federation transactions are not lost: the sending server keeps them queued
+    /// and retries them later.
+    pub fn ensure_writable(&self) -> Result<()> {
+        if self.maintenance_mode() {
+            return Err(Error::BadRequest(
+                ErrorKind::Unknown,
+                "Server is in maintenance mode.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn presence_enabled(&self) -> bool {
+        self.config.presence_enabled
+    }
+
+    pub fn startup_self_check(&self) -> bool {
+        self.config.startup_self_check
+    }
+
+    pub fn max_initial_sync_rooms(&self) -> Option<u32> {
+        self.config.max_initial_sync_rooms
+    }
+
     pub fn allow_registration(&self) -> bool {
         self.config.allow_registration
     }
@@ -366,3 +425,50 @@ fn reqwest_client_builder(config: &Config) -> Result<reqwest::ClientBuilder> {
 
     Ok(reqwest_client_builder)
 }
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::client::{error::ErrorKind, message::send_message_event, sync::sync_events},
+        events::room::message::RoomMessageEventContent,
+        TransactionId,
+    };
+
+    use crate::{
+        api::client_server::{send_message_event_route, sync_events_route},
+        services, testing, Error,
+    };
+
+    #[tokio::test]
+    async fn writes_are_refused_in_maintenance_mode() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+
+        services().globals.set_maintenance_mode(true);
+        let send = send_message_event_route(testing::request(
+            &alice,
+            send_message_event::v3::Request::new(
+                room_id.clone(),
+                TransactionId::new(),
+                &RoomMessageEventContent::text_plain("hello"),
+            )
+            .unwrap(),
+        ))
+        .await;
+        let sync =
+            sync_events_route(testing::request(&alice, sync_events::v3::Request::new())).await;
+        services().globals.set_maintenance_mode(false);
+
+        // Message sends are refused...
+        assert!(matches!(
+            send,
+            Err(Error::BadRequest(ErrorKind::Unknown, _))
+        ));
+        // ...while clients can still sync
+        match sync {
+            Ok(response) => assert!(response.rooms.join.contains_key(&room_id)),
+            Err(_) => panic!("sync failed in maintenance mode"),
+        }
+    }
+}
@@ -14,12 +14,15 @@ use ruma::{
         IncomingResponse, MatrixVersion, OutgoingRequest, SendAccessToken,
     },
     events::{
-        room::{name::RoomNameEventContent, power_levels::RoomPowerLevelsEventContent},
+        room::{
+            create::RoomCreateEventContent, name::RoomNameEventContent,
+            power_levels::RoomPowerLevelsEventContent,
+        },
         RoomEventType, StateEventType,
     },
     push::{Action, PushConditionRoomCtx, PushFormat, Ruleset, Tweak},
     serde::Raw,
-    uint, RoomId, UInt, UserId,
+    uint, OwnedUserId, RoomId, UInt, UserId,
 };
 
 use std::{fmt::Debug, mem};
@@ -143,16 +146,7 @@ impl Service {
         let mut notify = None;
         let mut tweaks = Vec::new();
 
-        let power_levels: RoomPowerLevelsEventContent = services()
-            .rooms
-            .state_accessor
-            .room_state_get(&pdu.room_id, &StateEventType::RoomPowerLevels, "")?
-            .map(|ev| {
-                serde_json::from_str(ev.content.get())
-                    .map_err(|_| Error::bad_database("invalid m.room.power_levels event"))
-            })
-            .transpose()?
-            .unwrap_or_default();
+        let power_levels = self.room_power_levels(&pdu.room_id)?;
 
         for action in self.get_actions(
             user,
@@ -188,6 +182,35 @@ impl Service {
         Ok(())
     }
 
+    /// Returns the power levels used to evaluate push rules, including the
+    /// `notifications.room` level needed for `@room` highlights.
+    ///
+    /// Rooms without a power levels event use the defaults from the spec, which give the
+    /// room creator power level 100.
+    pub fn room_power_levels(&self, room_id: &RoomId) -> Result<RoomPowerLevelsEventContent> {
+        if let Some(power_levels) = services().rooms.state_accessor.room_state_get(
+            room_id,
+            &StateEventType::RoomPowerLevels,
+            "",
+        )? {
+            return serde_json::from_str(power_levels.content.get())
+                .map_err(|_| Error::bad_database("invalid m.room.power_levels event"));
+        }
+
+        let creator = services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomCreate, "")?
+            .map(|create| {
+                serde_json::from_str::<RoomCreateEventContent>(create.content.get())
+                    .map(|content| content.creator)
+                    .map_err(|_| Error::bad_database("Invalid create event in db."))
+            })
+            .transpose()?;
+
+        Ok(default_power_levels(creator))
+    }
+
     #[tracing::instrument(skip(self, user, ruleset, pdu))]
     pub fn get_actions<'a>(
         &self,
@@ -210,7 +233,7 @@ impl Service {
             notification_power_levels: power_levels.notifications.clone(),
         };
 
-        Ok(ruleset.get_actions(pdu, &ctx))
+        Ok(evaluate_push_actions(ruleset, pdu, &ctx))
     }
 
     #[tracing::instrument(skip(self, unread, pusher, tweaks, event))]
@@ -299,3 +322,128 @@ impl Service {
         }
     }
 }
+
+/// Evaluates the push rules for an event, but never lets users be notified of their own events,
+/// no matter which rules match (e.g. an `@room` in your own message).
+fn evaluate_push_actions<'a>(
+    ruleset: &'a Ruleset,
+    pdu: &Raw<AnySyncTimelineEvent>,
+    ctx: &PushConditionRoomCtx,
+) -> &'a [Action] {
+    let sender = pdu.get_field::<OwnedUserId>("sender").ok().flatten();
+    if sender.as_deref() == Some(&*ctx.user_id) {
+        return &[];
+    }
+
+    ruleset.get_actions(pdu, ctx)
+}
+
+/// The power levels of a room without a power levels event.
+fn default_power_levels(creator: Option<OwnedUserId>) -> RoomPowerLevelsEventContent {
+    let mut power_levels = RoomPowerLevelsEventContent::default();
+    if let Some(creator) = creator {
+        power_levels.users.insert(creator, 100.into());
+    }
+    power_levels
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        events::room::power_levels::RoomPowerLevelsEventContent,
+        push::{Action, PushConditionRoomCtx, Ruleset, Tweak},
+        room_id,
+        serde::Raw,
+        user_id,
+    };
+    use serde_json::json;
+
+    use super::{default_power_levels, evaluate_push_actions};
+
+    #[test]
+    fn mentioning_yourself_does_not_highlight() {
+        let user = user_id!("@alice:example.org");
+        let ruleset = Ruleset::server_default(user);
+        let power_levels = RoomPowerLevelsEventContent::default();
+        let ctx = PushConditionRoomCtx {
+            room_id: room_id!("!room:example.org").to_owned(),
+            member_count: 10_u32.into(),
+            user_id: user.to_owned(),
+            user_display_name: "alice".to_owned(),
+            users_power_levels: power_levels.users.clone(),
+            default_power_level: power_levels.users_default,
+            notification_power_levels: power_levels.notifications,
+        };
+
+        let event = |sender: &str| {
+            Raw::new(&json!({
+                "type": "m.room.message",
+                "event_id": "$event:example.org",
+                "sender": sender,
+                "origin_server_ts": 1,
+                "content": { "msgtype": "m.text", "body": "hey alice" },
+            }))
+            .unwrap()
+            .cast()
+        };
+
+        let highlights = |actions: &[Action]| {
+            actions
+                .iter()
+                .any(|action| matches!(action, Action::SetTweak(Tweak::Highlight(true))))
+        };
+
+        assert!(highlights(evaluate_push_actions(
+            &ruleset,
+            &event("@bob:example.org"),
+            &ctx
+        )));
+        assert!(evaluate_push_actions(&ruleset, &event(user.as_str()), &ctx).is_empty());
+    }
+
+    #[test]
+    fn room_notifications_require_the_notifications_room_level() {
+        let user = user_id!("@alice:example.org");
+        let sender = user_id!("@bob:example.org");
+        let ruleset = Ruleset::server_default(user);
+        let ctx = |power_levels: RoomPowerLevelsEventContent| PushConditionRoomCtx {
+            room_id: room_id!("!room:example.org").to_owned(),
+            member_count: 10_u32.into(),
+            user_id: user.to_owned(),
+            user_display_name: "alice".to_owned(),
+            users_power_levels: power_levels.users,
+            default_power_level: power_levels.users_default,
+            notification_power_levels: power_levels.notifications,
+        };
+
+        let event = Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": "$event:example.org",
+            "sender": sender,
+            "origin_server_ts": 1,
+            "content": { "msgtype": "m.text", "body": "@room hello" },
+        }))
+        .unwrap()
+        .cast();
+
+        let highlights = |actions: &[Action]| {
+            actions
+                .iter()
+                .any(|action| matches!(action, Action::SetTweak(Tweak::Highlight(true))))
+        };
+
+        // Bob has the default user level 0, below the default notifications.room level of 50
+        assert!(!highlights(evaluate_push_actions(
+            &ruleset,
+            &event,
+            &ctx(RoomPowerLevelsEventContent::default())
+        )));
+
+        // The room creator gets power level 100 if the room has no power levels event
+        assert!(highlights(evaluate_push_actions(
+            &ruleset,
+            &event,
+            &ctx(default_power_levels(Some(sender.to_owned())))
+        )));
+    }
+}
@@ -2,7 +2,7 @@ mod data;
 
 pub use data::Data;
 
-use crate::Result;
+use crate::{services, Result};
 use ruma::{OwnedRoomAliasId, OwnedRoomId, RoomAliasId, RoomId};
 
 pub struct Service {
@@ -32,4 +32,62 @@ impl Service {
     ) -> Box<dyn Iterator<Item = Result<OwnedRoomAliasId>> + 'a> {
         self.db.local_aliases_for_room(room_id)
     }
+
+    /// Checks if the room already has as many local aliases as the config allows.
+    #[tracing::instrument(skip(self))]
+    pub fn alias_limit_reached(&self, room_id: &RoomId) -> Result<bool> {
+        let count = self.local_aliases_for_room(room_id).count();
+
+        Ok(count >= services().globals.max_aliases_per_room() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::client::{alias::create_alias, error::ErrorKind},
+        RoomAliasId,
+    };
+
+    use crate::{api::client_server::create_alias_route, services, testing, utils, Error};
+
+    #[tokio::test]
+    async fn aliases_up_to_the_limit_are_allowed() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let max_aliases = services().globals.max_aliases_per_room();
+        let prefix = utils::random_string(8).to_lowercase();
+
+        let create_alias = |i| {
+            let alias = RoomAliasId::parse(format!(
+                "#{}_{}:{}",
+                prefix,
+                i,
+                services().globals.server_name()
+            ))
+            .unwrap();
+            create_alias_route(testing::request(
+                &alice,
+                create_alias::v3::Request::new(alias, room_id.clone()),
+            ))
+        };
+
+        for i in 0..max_aliases {
+            create_alias(i).await.unwrap();
+        }
+
+        assert!(matches!(
+            create_alias(max_aliases).await,
+            Err(Error::BadRequest(ErrorKind::LimitExceeded { .. }, _))
+        ));
+        assert_eq!(
+            services()
+                .rooms
+                .alias
+                .local_aliases_for_room(&room_id)
+                .count(),
+            max_aliases as usize
+        );
+    }
 }
@@ -1,9 +1,18 @@
 mod data;
 
 pub use data::Data;
-use ruma::{OwnedRoomId, RoomId};
+use ruma::{
+    events::{
+        room::{
+            create::RoomCreateEventContent,
+            join_rules::{JoinRule, RoomJoinRulesEventContent},
+        },
+        StateEventType,
+    },
+    OwnedRoomId, RoomId,
+};
 
-use crate::Result;
+use crate::{services, Error, Result};
 
 pub struct Service {
     pub db: &'static dyn Data,
@@ -29,4 +38,106 @@ impl Service {
     pub fn public_rooms(&self) -> impl Iterator<Item = Result<OwnedRoomId>> + '_ {
         self.db.public_rooms()
     }
+
+    /// Whether a room may be shown in the directory we expose to other servers.
+    ///
+    /// The room has to be published, federated and joinable (or knockable) without an invite.
+    #[tracing::instrument(skip(self))]
+    pub fn is_publicly_listable(&self, room_id: &RoomId) -> Result<bool> {
+        if !self.is_public_room(room_id)? {
+            return Ok(false);
+        }
+
+        let federate = services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomCreate, "")?
+            .map(|s| {
+                serde_json::from_str::<RoomCreateEventContent>(s.content.get())
+                    .map(|c| c.federate)
+                    .map_err(|_| Error::bad_database("Invalid room create event in database."))
+            })
+            .transpose()?
+            .unwrap_or(false);
+
+        let join_rule = services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomJoinRules, "")?
+            .map(|s| {
+                serde_json::from_str::<RoomJoinRulesEventContent>(s.content.get())
+                    .map(|c| c.join_rule)
+                    .map_err(|_| Error::bad_database("Invalid room join rule event in database."))
+            })
+            .transpose()?
+            .unwrap_or(JoinRule::Invite);
+
+        Ok(publicly_listable(federate, &join_rule))
+    }
+}
+
+fn publicly_listable(federate: bool, join_rule: &JoinRule) -> bool {
+    federate
+        && matches!(
+            join_rule,
+            JoinRule::Public | JoinRule::Knock | JoinRule::KnockRestricted { .. }
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::client::room::create_room::{self, v3::RoomPreset},
+        directory::{Filter, RoomNetwork},
+        uint, OwnedRoomId,
+    };
+
+    use crate::{
+        api::client_server::{create_room_route, get_public_rooms_filtered_helper},
+        services, testing,
+    };
+
+    async fn listed_rooms(for_federation: bool) -> Vec<OwnedRoomId> {
+        get_public_rooms_filtered_helper(
+            None,
+            Some(uint!(1000)),
+            None,
+            &Filter::default(),
+            &RoomNetwork::Matrix,
+            for_federation,
+        )
+        .await
+        .unwrap()
+        .chunk
+        .into_iter()
+        .map(|room| room.room_id)
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn non_listable_rooms_are_not_federated() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+
+        let public = testing::public_room(&alice).await;
+        let mut body = create_room::v3::Request::new();
+        body.preset = Some(RoomPreset::PrivateChat);
+        let invite_only = create_room_route(testing::request(&alice, body))
+            .await
+            .unwrap()
+            .room_id;
+
+        for room_id in [&public, &invite_only] {
+            services().rooms.directory.set_public(room_id).unwrap();
+        }
+
+        let for_clients = listed_rooms(false).await;
+        assert!(for_clients.contains(&public));
+        assert!(for_clients.contains(&invite_only));
+
+        // Published, but requires an invite
+        let for_federation = listed_rooms(true).await;
+        assert!(for_federation.contains(&public));
+        assert!(!for_federation.contains(&invite_only));
+    }
 }
@@ -4,7 +4,7 @@ use std::collections::HashMap;
 pub use data::Data;
 use ruma::{events::presence::PresenceEvent, OwnedUserId, RoomId, UserId};
 
-use crate::Result;
+use crate::{services, Result};
 
 pub struct Service {
     pub db: &'static dyn Data,
@@ -21,11 +21,19 @@ impl Service {
         room_id: &RoomId,
         presence: PresenceEvent,
     ) -> Result<()> {
+        if !services().globals.presence_enabled() {
+            return Ok(());
+        }
+
         self.db.update_presence(user_id, room_id, presence)
     }
 
     /// Resets the presence timeout, so the user will stay in their current presence state.
     pub fn ping_presence(&self, user_id: &UserId) -> Result<()> {
+        if !services().globals.presence_enabled() {
+            return Ok(());
+        }
+
         self.db.ping_presence(user_id)
     }
 
@@ -34,6 +42,10 @@ impl Service {
         user_id: &UserId,
         room_id: &RoomId,
     ) -> Result<Option<PresenceEvent>> {
+        if !services().globals.presence_enabled() {
+            return Ok(None);
+        }
+
         let last_update = match self.db.last_presence_update(user_id)? {
             Some(last) => last,
             None => return Ok(None),
@@ -117,6 +129,49 @@ impl Service {
         room_id: &RoomId,
         since: u64,
     ) -> Result<HashMap<OwnedUserId, PresenceEvent>> {
+        if !services().globals.presence_enabled() {
+            return Ok(HashMap::new());
+        }
+
         self.db.presence_since(room_id, since)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::client::{presence::set_presence, sync::sync_events},
+        events::room::member::MembershipState,
+        presence::PresenceState,
+    };
+
+    use crate::{
+        api::client_server::{set_presence_route, sync_events_route},
+        services, testing,
+    };
+
+    #[tokio::test]
+    async fn disabled_presence_yields_no_sync_updates() {
+        let _db = testing::database().await;
+        assert!(!services().globals.presence_enabled());
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let room_id = testing::public_room(&alice).await;
+        testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+
+        set_presence_route(testing::request(
+            &bob,
+            set_presence::v3::Request::new(bob.clone(), PresenceState::Online),
+        ))
+        .await
+        .unwrap();
+
+        match sync_events_route(testing::request(&alice, sync_events::v3::Request::new())).await {
+            Ok(response) => {
+                assert!(response.rooms.join.contains_key(&room_id));
+                assert!(response.presence.is_empty());
+            }
+            Err(_) => panic!("sync failed"),
+        }
+    }
+}
@@ -33,4 +33,7 @@ pub trait Data: Send + Sync {
 
     /// Returns the count of the last typing update in this room.
     fn last_privateread_update(&self, user_id: &UserId, room_id: &RoomId) -> Result<u64>;
+
+    /// Removes all read receipts and private read markers of the room.
+    fn purge_room(&self, room_id: &RoomId) -> Result<()>;
 }
@@ -2,7 +2,7 @@ mod data;
 
 pub use data::Data;
 
-use crate::Result;
+use crate::{services, Result};
 use ruma::{events::receipt::ReceiptEvent, serde::Raw, OwnedUserId, RoomId, UserId};
 
 pub struct Service {
@@ -17,7 +17,12 @@ impl Service {
         room_id: &RoomId,
         event: ReceiptEvent,
     ) -> Result<()> {
-        self.db.readreceipt_update(user_id, room_id, event)
+        self.db.readreceipt_update(user_id, room_id, event)?;
+
+        services()
+            .rooms
+            .user
+            .mark_room_updated_for_local_users(room_id, services().globals.current_count()?)
     }
 
     /// Returns an iterator over the most recent read_receipts in a room that happened after the event with id `since`.
@@ -39,7 +44,13 @@ impl Service {
     /// Sets a private read marker at `count`.
     #[tracing::instrument(skip(self))]
     pub fn private_read_set(&self, room_id: &RoomId, user_id: &UserId, count: u64) -> Result<()> {
-        self.db.private_read_set(room_id, user_id, count)
+        self.db.private_read_set(room_id, user_id, count)?;
+
+        services().rooms.user.mark_room_updated(
+            user_id,
+            room_id,
+            services().globals.current_count()?,
+        )
     }
 
     /// Returns the private read marker.
@@ -48,6 +59,12 @@ impl Service {
         self.db.private_read_get(room_id, user_id)
     }
 
+    /// Removes all read receipts and private read markers of a purged room.
+    #[tracing::instrument(skip(self))]
+    pub fn purge_room(&self, room_id: &RoomId) -> Result<()> {
+        self.db.purge_room(room_id)
+    }
+
     /// Returns the count of the last typing update in this room.
     pub fn last_privateread_update(&self, user_id: &UserId, room_id: &RoomId) -> Result<u64> {
         self.db.last_privateread_update(user_id, room_id)
@@ -3,7 +3,7 @@ mod data;
 pub use data::Data;
 use ruma::{events::SyncEphemeralRoomEvent, RoomId, UserId};
 
-use crate::Result;
+use crate::{services, utils, Result};
 
 pub struct Service {
     pub db: &'static dyn Data,
@@ -12,7 +12,22 @@ pub struct Service {
 impl Service {
     /// Sets a user as typing until the timeout timestamp is reached or roomtyping_remove is
     /// called.
+    ///
+    /// The timeout is clamped to the minimum and maximum typing timeout from the config.
     pub fn typing_add(&self, user_id: &UserId, room_id: &RoomId, timeout: u64) -> Result<()> {
+        let timeout = clamp_timeout(
+            timeout,
+            utils::millis_since_unix_epoch(),
+            services()
+                .globals
+                .min_typing_timeout_s()
+                .saturating_mul(1000),
+            services()
+                .globals
+                .max_typing_timeout_s()
+                .saturating_mul(1000),
+        );
+
         self.db.typing_add(user_id, room_id, timeout)
     }
 
@@ -47,3 +62,27 @@ impl Service {
         })
     }
 }
+
+/// Makes sure the absolute `timeout` timestamp lies between `now + min` and `now + max`.
+fn clamp_timeout(timeout: u64, now: u64, min: u64, max: u64) -> u64 {
+    timeout
+        .min(now.saturating_add(max))
+        .max(now.saturating_add(min.min(max)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::clamp_timeout;
+
+    #[test]
+    fn long_timeouts_are_capped() {
+        let now = 1_000_000;
+
+        assert_eq!(
+            clamp_timeout(now + 10 * 60 * 1000, now, 1000, 30_000),
+            now + 30_000
+        );
+        assert_eq!(clamp_timeout(now + 5000, now, 1000, 30_000), now + 5000);
+        assert_eq!(clamp_timeout(now, now, 1000, 30_000), now + 1000);
+    }
+}
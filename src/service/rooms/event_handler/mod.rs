@@ -14,7 +14,7 @@ use std::{
 };
 use tokio::sync::Semaphore;
 
-use futures_util::{stream::FuturesUnordered, Future, StreamExt};
+use futures_util::{stream, Future, StreamExt};
 use ruma::{
     api::{
         client::error::ErrorKind,
@@ -1306,6 +1306,7 @@ impl Service {
 
         // We go through all the signatures we see on the value and fetch the corresponding signing
         // keys
+        let mut servers = Vec::new();
         for (signature_server, signature) in signatures {
             let signature_object = signature.as_object().ok_or(Error::BadServerResponse(
                 "Invalid signatures content object in server response pdu.",
@@ -1313,17 +1314,24 @@ impl Service {
 
             let signature_ids = signature_object.keys().cloned().collect::<Vec<_>>();
 
-            let fetch_res = self
-                .fetch_signing_keys(
-                    signature_server.as_str().try_into().map_err(|_| {
-                        Error::BadServerResponse(
-                            "Invalid servername in signatures of server response pdu.",
-                        )
-                    })?,
-                    signature_ids,
-                )
-                .await;
+            let origin: OwnedServerName = signature_server.as_str().try_into().map_err(|_| {
+                Error::BadServerResponse("Invalid servername in signatures of server response pdu.")
+            })?;
+
+            servers.push((origin, signature_ids));
+        }
 
+        let results = fetch_keys_concurrently(
+            servers,
+            services().globals.max_fetch_keys_servers().into(),
+            |(origin, signature_ids)| async move {
+                let fetch_res = self.fetch_signing_keys(&origin, signature_ids).await;
+                (origin, fetch_res)
+            },
+        )
+        .await;
+
+        for (origin, fetch_res) in results {
             let keys = match fetch_res {
                 Ok(keys) => keys,
                 Err(_) => {
@@ -1335,7 +1343,7 @@ impl Service {
             pub_key_map
                 .write()
                 .map_err(|_| Error::bad_database("RwLock is poisoned."))?
-                .insert(signature_server.clone(), keys);
+                .insert(origin.to_string(), keys);
         }
 
         Ok(())
@@ -1514,9 +1522,10 @@ impl Service {
         }
 
         info!("Asking individual servers for signing keys: {servers:?}");
-        let mut futures: FuturesUnordered<_> = servers
-            .into_keys()
-            .map(|server| async move {
+        let results = fetch_keys_concurrently(
+            servers.into_keys(),
+            services().globals.max_fetch_keys_servers().into(),
+            |server| async move {
                 (
                     services()
                         .sending
@@ -1524,10 +1533,11 @@ impl Service {
                         .await,
                     server,
                 )
-            })
-            .collect();
+            },
+        )
+        .await;
 
-        while let Some(result) = futures.next().await {
+        for result in results {
             info!("Received new result");
             if let (Ok(get_keys_response), origin) = result {
                 info!("Result is from {origin}");
@@ -1748,3 +1758,207 @@ impl Service {
         ))
     }
 }
+
+/// Runs `fetch` for every server, with at most `limit` requests in flight at once. Results come
+/// back in the order they finish.
+async fn fetch_keys_concurrently<I, F, Fut>(servers: I, limit: usize, fetch: F) -> Vec<Fut::Output>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> Fut,
+    Fut: Future,
+{
+    stream::iter(servers)
+        .map(fetch)
+        .buffer_unordered(limit.max(1))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        api::federation::discovery::{ServerSigningKeys, VerifyKey},
+        events::{
+            room::{member::MembershipState, message::RoomMessageEventContent},
+            RoomEventType,
+        },
+        UserId,
+    };
+    use serde_json::value::to_raw_value;
+
+    use super::*;
+    use crate::{service::pdu::PduBuilder, testing};
+
+    /// Lets our own events pass signature verification as if they came in over federation.
+    fn trust_own_signing_key() {
+        let server_name = services().globals.server_name();
+        let mut verify_keys = BTreeMap::new();
+        verify_keys.insert(
+            format!("ed25519:{}", services().globals.keypair().version())
+                .try_into()
+                .expect("key id is valid"),
+            VerifyKey {
+                key: Base64::new(services().globals.keypair().public_key().to_vec()),
+            },
+        );
+
+        services()
+            .globals
+            .add_signing_key(
+                server_name,
+                ServerSigningKeys {
+                    server_name: server_name.to_owned(),
+                    verify_keys,
+                    old_verify_keys: BTreeMap::new(),
+                    signatures: BTreeMap::new(),
+                    valid_until_ts: MilliSecondsSinceUnixEpoch::from_system_time(
+                        SystemTime::now() + Duration::from_secs(3600),
+                    )
+                    .expect("time is valid"),
+                },
+            )
+            .unwrap();
+    }
+
+    /// Signs a message from `sender` against the current room state without appending it.
+    async fn signed_message(
+        room_id: &RoomId,
+        sender: &UserId,
+    ) -> (Arc<EventId>, CanonicalJsonObject) {
+        let mutex_state = Arc::clone(
+            services()
+                .globals
+                .roomid_mutex_state
+                .write()
+                .unwrap()
+                .entry(room_id.to_owned())
+                .or_default(),
+        );
+        let state_lock = mutex_state.lock().await;
+
+        let (pdu, mut pdu_json) = services()
+            .rooms
+            .timeline
+            .create_hash_and_sign_event(
+                PduBuilder {
+                    event_type: RoomEventType::RoomMessage,
+                    content: to_raw_value(&RoomMessageEventContent::text_plain("hello")).unwrap(),
+                    unsigned: None,
+                    state_key: None,
+                    redacts: None,
+                },
+                sender,
+                room_id,
+                &state_lock,
+            )
+            .unwrap();
+
+        // Like it would arrive over federation
+        pdu_json.remove("event_id");
+
+        (pdu.event_id, pdu_json)
+    }
+
+    async fn receive(
+        room_id: &RoomId,
+        event_id: &EventId,
+        pdu_json: CanonicalJsonObject,
+    ) -> Result<Option<Vec<u8>>> {
+        services()
+            .rooms
+            .event_handler
+            .handle_incoming_pdu(
+                services().globals.server_name(),
+                event_id,
+                room_id,
+                pdu_json,
+                true,
+                &RwLock::new(BTreeMap::new()),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn forged_signature_is_rejected() {
+        let _db = testing::database().await;
+        trust_own_signing_key();
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+
+        let (event_id, mut pdu_json) = signed_message(&room_id, &alice).await;
+        let forged = CanonicalJsonValue::String(Base64::new(vec![0_u8; 64]).encode());
+        for signatures in pdu_json
+            .get_mut("signatures")
+            .and_then(CanonicalJsonValue::as_object_mut)
+            .unwrap()
+            .values_mut()
+        {
+            for signature in signatures.as_object_mut().unwrap().values_mut() {
+                *signature = forged.clone();
+            }
+        }
+
+        assert!(receive(&room_id, &event_id, pdu_json).await.is_err());
+        assert!(services()
+            .rooms
+            .timeline
+            .get_pdu(&event_id)
+            .unwrap()
+            .is_none());
+        assert!(!services()
+            .rooms
+            .pdu_metadata
+            .is_event_soft_failed(&event_id)
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn send_after_ban_is_soft_failed() {
+        let _db = testing::database().await;
+        trust_own_signing_key();
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let room_id = testing::public_room(&alice).await;
+        testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+
+        // Authorized by its auth events, but bob is banned before it arrives
+        let (event_id, pdu_json) = signed_message(&room_id, &bob).await;
+        testing::membership(&room_id, &alice, &bob, MembershipState::Ban).await;
+
+        receive(&room_id, &event_id, pdu_json).await.unwrap();
+
+        assert!(services()
+            .rooms
+            .pdu_metadata
+            .is_event_soft_failed(&event_id)
+            .unwrap());
+        assert!(services()
+            .rooms
+            .timeline
+            .get_pdu_id(&event_id)
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn keys_are_fetched_from_servers_concurrently() {
+        let servers = ["a.example.org", "b.example.org", "c.example.org"];
+        // Every server only answers once all of them have been asked
+        let all_asked = tokio::sync::Barrier::new(servers.len());
+
+        let fetched = tokio::time::timeout(
+            Duration::from_secs(5),
+            fetch_keys_concurrently(servers, servers.len(), |server| {
+                let all_asked = &all_asked;
+                async move {
+                    all_asked.wait().await;
+                    server
+                }
+            }),
+        )
+        .await
+        .expect("servers were not queried at the same time");
+
+        assert_eq!(fetched.len(), servers.len());
+    }
+}
@@ -1,3 +1,9 @@
+use std::{fmt, mem::size_of};
+
+use ruma::{events::StateEventType, OwnedRoomId, RoomId};
+
+use crate::{utils, Result};
+
 pub mod alias;
 pub mod auth_chain;
 pub mod directory;
@@ -55,3 +61,297 @@ pub struct Service {
     pub timeline: timeline::Service,
     pub user: user::Service,
 }
+
+/// A problem with the stored room data found by [`Service::self_check`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvariantViolation {
+    MissingCreateEvent {
+        room_id: OwnedRoomId,
+    },
+    JoinedCountMismatch {
+        room_id: OwnedRoomId,
+        stored: Option<u64>,
+        scanned: u64,
+    },
+    BrokenStateChain {
+        room_id: OwnedRoomId,
+        shortstatehash: u64,
+    },
+    UnresolvableStateEvent {
+        room_id: OwnedRoomId,
+        shortstatekey: u64,
+    },
+    StateKeyMismatch {
+        room_id: OwnedRoomId,
+        shortstatekey: u64,
+    },
+    EventIdMismatch {
+        room_id: OwnedRoomId,
+        shorteventid: u64,
+    },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingCreateEvent { room_id } => {
+                write!(f, "{room_id} has no create event in its state")
+            }
+            Self::JoinedCountMismatch {
+                room_id,
+                stored,
+                scanned,
+            } => write!(
+                f,
+                "{room_id} has a stored joined count of {stored:?}, but {scanned} joined members"
+            ),
+            Self::BrokenStateChain {
+                room_id,
+                shortstatehash,
+            } => write!(
+                f,
+                "{room_id} has state {shortstatehash} that can't be loaded from its parents"
+            ),
+            Self::UnresolvableStateEvent {
+                room_id,
+                shortstatekey,
+            } => write!(
+                f,
+                "{room_id} has a state entry {shortstatekey} whose short ids don't map back to an event"
+            ),
+            Self::StateKeyMismatch {
+                room_id,
+                shortstatekey,
+            } => write!(
+                f,
+                "{room_id} has a state key whose short id doesn't lead back to {shortstatekey}"
+            ),
+            Self::EventIdMismatch {
+                room_id,
+                shorteventid,
+            } => write!(
+                f,
+                "{room_id} has a state event whose short id doesn't lead back to {shorteventid}"
+            ),
+        }
+    }
+}
+
+impl Service {
+    /// Checks that the stored data of every room is consistent:
+    /// - The room state contains a create event
+    /// - The stored joined count matches the joined members
+    /// - The current state can be loaded through all its parent diffs
+    /// - Every short id in the current state maps to a state key and event, and back
+    ///
+    /// Rooms without a current state, like ones we were only invited to, are skipped.
+    /// Violations are only reported, nothing is repaired.
+    #[tracing::instrument(skip(self))]
+    pub fn self_check(&self) -> Result<Vec<InvariantViolation>> {
+        let mut violations = Vec::new();
+
+        for room_id in self.metadata.iter_ids() {
+            let room_id = room_id?;
+
+            let shortstatehash = match self.state.get_room_shortstatehash(&room_id)? {
+                Some(shortstatehash) => shortstatehash,
+                None => continue,
+            };
+
+            violations.extend(joined_count_violation(
+                &room_id,
+                self.state_cache.room_joined_count(&room_id)?,
+                self.state_cache.room_members(&room_id).count() as u64,
+            ));
+
+            let chain_violations = self.verify_state_chain(&room_id, shortstatehash);
+
+            // Reading the create event needs an intact state
+            if chain_violations.is_empty()
+                && self
+                    .state_accessor
+                    .room_state_get(&room_id, &StateEventType::RoomCreate, "")?
+                    .is_none()
+            {
+                violations.push(InvariantViolation::MissingCreateEvent {
+                    room_id: room_id.clone(),
+                });
+            }
+
+            violations.extend(chain_violations);
+        }
+
+        Ok(violations)
+    }
+
+    /// Loads the state `shortstatehash` with all its parent layers and resolves each entry.
+    fn verify_state_chain(&self, room_id: &RoomId, shortstatehash: u64) -> Vec<InvariantViolation> {
+        let full_state = match self
+            .state_compressor
+            .load_shortstatehash_info(shortstatehash)
+        {
+            Ok(mut info) => info.pop().expect("there is always one layer").1,
+            Err(_) => {
+                return vec![InvariantViolation::BrokenStateChain {
+                    room_id: room_id.to_owned(),
+                    shortstatehash,
+                }]
+            }
+        };
+
+        let mut violations = Vec::new();
+        for compressed in full_state.iter() {
+            let shortstatekey = utils::u64_from_bytes(&compressed[0..size_of::<u64>()])
+                .expect("bytes have right length");
+            let shorteventid = utils::u64_from_bytes(&compressed[size_of::<u64>()..])
+                .expect("bytes have right length");
+
+            let state_key = self.short.get_statekey_from_short(shortstatekey).ok();
+            let event_id = self.short.get_eventid_from_short(shorteventid).ok();
+
+            if let Some((event_type, state_key)) = &state_key {
+                if self
+                    .short
+                    .get_shortstatekey(event_type, state_key)
+                    .ok()
+                    .flatten()
+                    != Some(shortstatekey)
+                {
+                    violations.push(InvariantViolation::StateKeyMismatch {
+                        room_id: room_id.to_owned(),
+                        shortstatekey,
+                    });
+                }
+            }
+
+            if let Some(event_id) = &event_id {
+                if self.short.get_shorteventid(event_id).ok().flatten() != Some(shorteventid) {
+                    violations.push(InvariantViolation::EventIdMismatch {
+                        room_id: room_id.to_owned(),
+                        shorteventid,
+                    });
+                }
+            }
+
+            let resolves = state_key.is_some()
+                && event_id.map_or(false, |event_id| {
+                    matches!(self.timeline.get_pdu(&event_id), Ok(Some(_)))
+                });
+            if !resolves {
+                violations.push(InvariantViolation::UnresolvableStateEvent {
+                    room_id: room_id.to_owned(),
+                    shortstatekey,
+                });
+            }
+        }
+
+        violations
+    }
+}
+
+fn joined_count_violation(
+    room_id: &RoomId,
+    stored: Option<u64>,
+    scanned: u64,
+) -> Option<InvariantViolation> {
+    (stored.unwrap_or(0) != scanned).then(|| InvariantViolation::JoinedCountMismatch {
+        room_id: room_id.to_owned(),
+        stored,
+        scanned,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::room_id;
+
+    use super::*;
+    use crate::{services, testing};
+
+    #[test]
+    fn wrong_joined_count_is_reported() {
+        let room_id = room_id!("!room:example.org");
+
+        assert_eq!(joined_count_violation(room_id, Some(2), 2), None);
+        assert_eq!(
+            joined_count_violation(room_id, Some(3), 2),
+            Some(InvariantViolation::JoinedCountMismatch {
+                room_id: room_id.to_owned(),
+                stored: Some(3),
+                scanned: 2,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn rooms_without_state_are_skipped() {
+        let _db = testing::database().await;
+        let room_id = RoomId::parse(format!(
+            "!{}:{}",
+            utils::random_string(8),
+            services().globals.server_name()
+        ))
+        .unwrap();
+        services()
+            .rooms
+            .short
+            .get_or_create_shortroomid(&room_id)
+            .unwrap();
+
+        assert!(!services()
+            .rooms
+            .self_check()
+            .unwrap()
+            .iter()
+            .any(|violation| format!("{violation}").contains(room_id.as_str())));
+    }
+
+    #[tokio::test]
+    async fn event_id_that_maps_to_another_short_id_is_reported() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let create_event = services()
+            .rooms
+            .state_accessor
+            .room_state_get(&room_id, &StateEventType::RoomCreate, "")
+            .unwrap()
+            .unwrap();
+        let shorteventid = services()
+            .rooms
+            .short
+            .get_shorteventid(&create_event.event_id)
+            .unwrap()
+            .unwrap();
+        assert!(services()
+            .rooms
+            .self_check()
+            .unwrap()
+            .iter()
+            .all(|violation| {
+                *violation
+                    != InvariantViolation::EventIdMismatch {
+                        room_id: room_id.clone(),
+                        shorteventid,
+                    }
+            }));
+
+        // Like a write that only reached one of the two trees
+        services()
+            .rooms
+            .short
+            .set_shorteventid(&create_event.event_id, u64::MAX)
+            .unwrap();
+        let violations = services().rooms.self_check().unwrap();
+        services()
+            .rooms
+            .short
+            .set_shorteventid(&create_event.event_id, shorteventid)
+            .unwrap();
+
+        assert!(violations.contains(&InvariantViolation::EventIdMismatch {
+            room_id,
+            shorteventid,
+        }));
+    }
+}
@@ -16,6 +16,11 @@ impl Service {
         self.db.mark_as_referenced(room_id, event_ids)
     }
 
+    #[tracing::instrument(skip(self, room_id, event_ids))]
+    pub fn unmark_as_referenced(&self, room_id: &RoomId, event_ids: &[Arc<EventId>]) -> Result<()> {
+        self.db.unmark_as_referenced(room_id, event_ids)
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn is_event_referenced(&self, room_id: &RoomId, event_id: &EventId) -> Result<bool> {
         self.db.is_event_referenced(room_id, event_id)
@@ -1,9 +1,16 @@
+use std::collections::HashSet;
+
 use crate::Result;
 use ruma::RoomId;
 
 pub trait Data: Send + Sync {
     fn index_pdu(&self, shortroomid: u64, pdu_id: &[u8], message_body: &str) -> Result<()>;
 
+    fn clear_room_index(&self, shortroomid: u64) -> Result<()>;
+
+    /// Removes the words of all messages of the room except `pdu_ids` from the index.
+    fn retain_room_index(&self, shortroomid: u64, pdu_ids: &HashSet<Vec<u8>>) -> Result<()>;
+
     fn search_pdus<'a>(
         &'a self,
         room_id: &RoomId,
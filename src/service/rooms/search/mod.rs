@@ -2,8 +2,13 @@ mod data;
 
 pub use data::Data;
 
-use crate::Result;
-use ruma::RoomId;
+use std::collections::HashSet;
+
+use crate::{services, Error, Result};
+use ruma::{events::RoomEventType, user_id, RoomId};
+use serde::Deserialize;
+use serde_json::value::RawValue as RawJsonValue;
+use tracing::warn;
 
 pub struct Service {
     pub db: &'static dyn Data,
@@ -15,6 +20,12 @@ impl Service {
         self.db.index_pdu(shortroomid, pdu_id, message_body)
     }
 
+    /// Removes all messages of a room from the search index.
+    #[tracing::instrument(skip(self))]
+    pub fn clear_room_index(&self, shortroomid: u64) -> Result<()> {
+        self.db.clear_room_index(shortroomid)
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn search_pdus<'a>(
         &'a self,
@@ -23,4 +34,163 @@ impl Service {
     ) -> Result<Option<(impl Iterator<Item = Vec<u8>> + 'a, Vec<String>)>> {
         self.db.search_pdus(room_id, search_string)
     }
+
+    /// Rebuilds the search index of a room from its messages and returns how many were indexed.
+    ///
+    /// Messages are indexed over the existing entries, so the room stays searchable while this
+    /// runs and it can safely be run again if it was interrupted. Entries of messages that are
+    /// gone or were redacted in the meantime are only dropped at the end.
+    #[tracing::instrument(skip(self))]
+    pub fn reindex_room(&self, room_id: &RoomId) -> Result<usize> {
+        let shortroomid = match services().rooms.short.get_shortroomid(room_id)? {
+            Some(shortroomid) => shortroomid,
+            None => return Ok(0),
+        };
+
+        let mut indexed = HashSet::new();
+        for pdu in services()
+            .rooms
+            .timeline
+            .all_pdus(user_id!("@doesntmatter:conduit.rs"), room_id)?
+        {
+            let (_, pdu) = pdu?;
+
+            if pdu.kind != RoomEventType::RoomMessage {
+                continue;
+            }
+
+            let body = match message_body(&pdu.content) {
+                Ok(Some(body)) => body,
+                Ok(None) => continue,
+                Err(_) => {
+                    warn!("Skipping message {} with invalid body", pdu.event_id);
+                    continue;
+                }
+            };
+
+            let pdu_id = match services().rooms.timeline.get_pdu_id(&pdu.event_id)? {
+                Some(pdu_id) => pdu_id,
+                None => {
+                    warn!("Timeline event {} has no pdu id", pdu.event_id);
+                    continue;
+                }
+            };
+
+            self.index_pdu(shortroomid, &pdu_id, &body)?;
+            indexed.insert(pdu_id);
+        }
+
+        self.db.retain_room_index(shortroomid, &indexed)?;
+
+        Ok(indexed.len())
+    }
+
+    /// Rebuilds the search index of every room this server knows about.
+    #[tracing::instrument(skip(self))]
+    pub fn reindex_all(&self) -> Result<usize> {
+        let mut indexed = 0;
+        for room_id in services().rooms.metadata.iter_ids() {
+            indexed += self.reindex_room(&room_id?)?;
+        }
+
+        Ok(indexed)
+    }
+}
+
+/// Returns the body of a `m.room.message` event, if it has one.
+fn message_body(content: &RawJsonValue) -> Result<Option<String>> {
+    #[derive(Deserialize)]
+    struct ExtractBody {
+        body: Option<String>,
+    }
+
+    serde_json::from_str::<ExtractBody>(content.get())
+        .map(|content| content.body)
+        .map_err(|_| Error::bad_database("Invalid content in pdu."))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{services, testing};
+
+    #[tokio::test]
+    async fn reindexing_makes_earlier_messages_searchable() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let event_id = testing::message(&room_id, &alice, "a needle in a haystack").await;
+        let pdu_id = services()
+            .rooms
+            .timeline
+            .get_pdu_id(&event_id)
+            .unwrap()
+            .unwrap();
+        let shortroomid = services()
+            .rooms
+            .short
+            .get_shortroomid(&room_id)
+            .unwrap()
+            .unwrap();
+
+        let search = || {
+            services()
+                .rooms
+                .search
+                .search_pdus(&room_id, "needle")
+                .unwrap()
+                .map_or_else(Vec::new, |(results, _)| results.collect())
+        };
+
+        // Like a message that was sent before it was indexed
+        services()
+            .rooms
+            .search
+            .clear_room_index(shortroomid)
+            .unwrap();
+        assert!(search().is_empty());
+
+        assert_eq!(services().rooms.search.reindex_room(&room_id).unwrap(), 1);
+        assert_eq!(search(), vec![pdu_id]);
+    }
+
+    #[tokio::test]
+    async fn reindexing_drops_only_stale_entries() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let event_id = testing::message(&room_id, &alice, "a needle in a haystack").await;
+        let pdu_id = services()
+            .rooms
+            .timeline
+            .get_pdu_id(&event_id)
+            .unwrap()
+            .unwrap();
+        let shortroomid = services()
+            .rooms
+            .short
+            .get_shortroomid(&room_id)
+            .unwrap()
+            .unwrap();
+
+        let search = || {
+            services()
+                .rooms
+                .search
+                .search_pdus(&room_id, "needle")
+                .unwrap()
+                .map_or_else(Vec::new, |(results, _)| results.collect::<Vec<_>>())
+        };
+
+        // Like a message that was indexed but no longer exists
+        let gone = [shortroomid.to_be_bytes(), u64::MAX.to_be_bytes()].concat();
+        services()
+            .rooms
+            .search
+            .index_pdu(shortroomid, &gone, "needle")
+            .unwrap();
+        assert_eq!(search().len(), 2);
+
+        assert_eq!(services().rooms.search.reindex_room(&room_id).unwrap(), 1);
+        assert_eq!(search(), vec![pdu_id]);
+    }
 }
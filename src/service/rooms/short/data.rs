@@ -6,6 +6,14 @@ use ruma::{events::StateEventType, EventId, RoomId};
 pub trait Data: Send + Sync {
     fn get_or_create_shorteventid(&self, event_id: &EventId) -> Result<u64>;
 
+    fn get_shorteventid(&self, event_id: &EventId) -> Result<Option<u64>>;
+
+    fn remove_shorteventid(&self, event_id: &EventId, shorteventid: u64) -> Result<()>;
+
+    /// Points the event id at `shorteventid` without updating the reverse mapping.
+    #[cfg(test)]
+    fn set_shorteventid(&self, event_id: &EventId, shorteventid: u64) -> Result<()>;
+
     fn get_shortstatekey(
         &self,
         event_type: &StateEventType,
@@ -28,4 +36,6 @@ pub trait Data: Send + Sync {
     fn get_shortroomid(&self, room_id: &RoomId) -> Result<Option<u64>>;
 
     fn get_or_create_shortroomid(&self, room_id: &RoomId) -> Result<u64>;
+
+    fn remove_shortroomid(&self, room_id: &RoomId) -> Result<()>;
 }
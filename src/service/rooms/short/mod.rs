@@ -15,6 +15,20 @@ impl Service {
         self.db.get_or_create_shorteventid(event_id)
     }
 
+    pub fn get_shorteventid(&self, event_id: &EventId) -> Result<Option<u64>> {
+        self.db.get_shorteventid(event_id)
+    }
+
+    /// Forgets the short id of an event in both directions.
+    pub fn remove_shorteventid(&self, event_id: &EventId, shorteventid: u64) -> Result<()> {
+        self.db.remove_shorteventid(event_id, shorteventid)
+    }
+
+    #[cfg(test)]
+    pub fn set_shorteventid(&self, event_id: &EventId, shorteventid: u64) -> Result<()> {
+        self.db.set_shorteventid(event_id, shorteventid)
+    }
+
     pub fn get_shortstatekey(
         &self,
         event_type: &StateEventType,
@@ -51,4 +65,9 @@ impl Service {
     pub fn get_or_create_shortroomid(&self, room_id: &RoomId) -> Result<u64> {
         self.db.get_or_create_shortroomid(room_id)
     }
+
+    /// Forgets the short id of a room, after which the room no longer exists on this server.
+    pub fn remove_shortroomid(&self, room_id: &RoomId) -> Result<()> {
+        self.db.remove_shortroomid(room_id)
+    }
 }
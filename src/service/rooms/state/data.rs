@@ -15,6 +15,15 @@ pub trait Data: Send + Sync {
         _mutex_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
     ) -> Result<()>;
 
+    /// Forgets the current state and forward extremities of the room and the state at the given
+    /// events.
+    fn delete_room_state(
+        &self,
+        room_id: &RoomId,
+        shorteventids: &[u64],
+        _mutex_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
+    ) -> Result<()>;
+
     /// Associates a state with an event.
     fn set_event_state(&self, shorteventid: u64, shortstatehash: u64) -> Result<()>;
 
@@ -1,6 +1,7 @@
 mod data;
 use std::{
     collections::{HashMap, HashSet},
+    fmt::Debug,
     sync::Arc,
 };
 
@@ -36,6 +37,8 @@ impl Service {
         _statediffremoved: HashSet<CompressedStateEvent>,
         state_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
     ) -> Result<()> {
+        let mut member_updates = Vec::new();
+
         for event_id in statediffnew.into_iter().filter_map(|new| {
             services()
                 .rooms
@@ -44,22 +47,15 @@ impl Service {
                 .ok()
                 .map(|(_, id)| id)
         }) {
-            let pdu = match services().rooms.timeline.get_pdu_json(&event_id)? {
+            let pdu = match services().rooms.timeline.get_pdu(&event_id)? {
                 Some(pdu) => pdu,
                 None => continue,
             };
 
-            if pdu.get("type").and_then(|val| val.as_str()) != Some("m.room.member") {
+            if pdu.kind != RoomEventType::RoomMember {
                 continue;
             }
 
-            let pdu: PduEvent = match serde_json::from_str(
-                &serde_json::to_string(&pdu).expect("CanonicalJsonObj can be serialized to JSON"),
-            ) {
-                Ok(pdu) => pdu,
-                Err(_) => continue,
-            };
-
             #[derive(Deserialize)]
             struct ExtractMembership {
                 membership: MembershipState,
@@ -70,7 +66,7 @@ impl Service {
                 Err(_) => continue,
             };
 
-            let state_key = match pdu.state_key {
+            let state_key = match &pdu.state_key {
                 Some(k) => k,
                 None => continue,
             };
@@ -80,15 +76,23 @@ impl Service {
                 Err(_) => continue,
             };
 
+            member_updates.push((user_id, membership, pdu.sender.clone()));
+        }
+
+        // A single bad member should not leave the rest of the state half-applied
+        apply_skipping_failures(member_updates, |(user_id, membership, sender)| {
+            // The state is authoritative, e.g. a lower depth ban chosen by state resolution must
+            // still apply, so it bypasses the ordering check
             services().rooms.state_cache.update_membership(
                 room_id,
-                &user_id,
-                membership,
-                &pdu.sender,
+                user_id,
+                membership.clone(),
+                sender,
+                None,
                 None,
                 false,
-            )?;
-        }
+            )
+        });
 
         services().rooms.state_cache.update_joined_count(room_id)?;
 
@@ -311,6 +315,80 @@ impl Service {
         self.db.set_room_state(room_id, shortstatehash, mutex_lock)
     }
 
+    /// Undoes the creation of a local room by deleting everything its events left behind, after
+    /// which the room no longer exists on this server.
+    ///
+    /// This is a compensating delete for rooms that no other server has joined yet, the database
+    /// has no transactions spanning all of these trees. Events were already queued for
+    /// appservices interested in the room, those can't be taken back.
+    #[tracing::instrument(skip(self, mutex_lock))]
+    pub fn purge_room(
+        &self,
+        room_id: &RoomId,
+        event_ids: &[Arc<EventId>],
+        mutex_lock: &MutexGuard<'_, ()>, // Take mutex guard to make sure users get the room state mutex
+    ) -> Result<()> {
+        let shortroomid = services().rooms.short.get_shortroomid(room_id)?;
+
+        let members: Vec<_> = services()
+            .rooms
+            .state_cache
+            .room_members(room_id)
+            .chain(services().rooms.state_cache.room_members_invited(room_id))
+            .filter_map(|r| r.ok())
+            .collect();
+
+        for user_id in &members {
+            services().rooms.state_cache.update_membership(
+                room_id,
+                user_id,
+                MembershipState::Leave,
+                user_id,
+                None,
+                None,
+                false,
+            )?;
+        }
+        services().rooms.state_cache.update_joined_count(room_id)?;
+        services().rooms.state_cache.purge_room(room_id, &members)?;
+        services().rooms.user.purge_room(room_id, &members)?;
+        services().rooms.edus.read_receipt.purge_room(room_id)?;
+
+        let mut shorteventids = Vec::new();
+        for event_id in event_ids {
+            services().rooms.timeline.remove_pdu(room_id, event_id)?;
+            if let Some(shorteventid) = services().rooms.short.get_shorteventid(event_id)? {
+                shorteventids.push((event_id, shorteventid));
+            }
+        }
+        services()
+            .rooms
+            .pdu_metadata
+            .unmark_as_referenced(room_id, event_ids)?;
+
+        if let Some(shortroomid) = shortroomid {
+            services().rooms.search.clear_room_index(shortroomid)?;
+        }
+
+        self.db.delete_room_state(
+            room_id,
+            &shorteventids
+                .iter()
+                .map(|(_, shorteventid)| *shorteventid)
+                .collect::<Vec<_>>(),
+            mutex_lock,
+        )?;
+
+        for (event_id, shorteventid) in shorteventids {
+            services()
+                .rooms
+                .short
+                .remove_shorteventid(event_id, shorteventid)?;
+        }
+
+        services().rooms.short.remove_shortroomid(room_id)
+    }
+
     /// Returns the room's version.
     #[tracing::instrument(skip(self))]
     pub fn get_room_version(&self, room_id: &RoomId) -> Result<RoomVersionId> {
@@ -419,3 +497,116 @@ impl Service {
             .collect())
     }
 }
+
+/// Calls `apply` for every item. Failures are logged and skipped instead of aborting the
+/// remaining items. Returns the number of items that failed.
+fn apply_skipping_failures<T: Debug>(
+    items: impl IntoIterator<Item = T>,
+    mut apply: impl FnMut(&T) -> Result<()>,
+) -> usize {
+    let mut failed = 0;
+    for item in items {
+        if let Err(e) = apply(&item) {
+            warn!("Failed to apply {:?}, skipping: {}", item, e);
+            failed += 1;
+        }
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ruma::events::{room::member::MembershipState, GlobalAccountDataEventType};
+    use serde_json::json;
+
+    use crate::{services, testing};
+
+    #[tokio::test]
+    async fn one_failing_member_does_not_stop_the_others() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let carol = testing::user("carol");
+        let room_id = testing::public_room(&alice).await;
+        testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+        testing::membership(&room_id, &alice, &carol, MembershipState::Invite).await;
+
+        // Applying carol's invite fails because her ignored users can't be read
+        services()
+            .account_data
+            .update(
+                None,
+                &carol,
+                GlobalAccountDataEventType::IgnoredUserList
+                    .to_string()
+                    .into(),
+                &json!({
+                    "type": "m.ignored_user_list",
+                    "content": { "ignored_users": "invalid" },
+                }),
+            )
+            .unwrap();
+
+        // The membership cache lost bob's join
+        services()
+            .rooms
+            .state_cache
+            .update_membership(
+                &room_id,
+                &bob,
+                MembershipState::Leave,
+                &bob,
+                None,
+                None,
+                true,
+            )
+            .unwrap();
+
+        let shortstatehash = services()
+            .rooms
+            .state
+            .get_room_shortstatehash(&room_id)
+            .unwrap()
+            .unwrap();
+        let full_state = services()
+            .rooms
+            .state_compressor
+            .load_shortstatehash_info(shortstatehash)
+            .unwrap()
+            .pop()
+            .unwrap()
+            .1;
+
+        let mutex_state = Arc::clone(
+            services()
+                .globals
+                .roomid_mutex_state
+                .write()
+                .unwrap()
+                .entry(room_id.clone())
+                .or_default(),
+        );
+        let state_lock = mutex_state.lock().await;
+
+        services()
+            .rooms
+            .state
+            .force_state(
+                &room_id,
+                shortstatehash,
+                full_state,
+                Default::default(),
+                &state_lock,
+            )
+            .await
+            .unwrap();
+
+        assert!(services()
+            .rooms
+            .state_cache
+            .is_joined(&bob, &room_id)
+            .unwrap());
+    }
+}
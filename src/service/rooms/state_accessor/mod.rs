@@ -1,12 +1,14 @@
 mod data;
 use std::{
     collections::HashMap,
+    hash::Hash,
     sync::{Arc, Mutex},
 };
 
 pub use data::Data;
 use lru_cache::LruCache;
 use ruma::{
+    api::client::error::ErrorKind,
     events::{
         room::{
             history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
@@ -16,6 +18,7 @@ use ruma::{
     },
     EventId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
 };
+use serde_json::value::RawValue as RawJsonValue;
 use tracing::error;
 
 use crate::{services, Error, PduEvent, Result};
@@ -24,6 +27,7 @@ pub struct Service {
     pub db: &'static dyn Data,
     pub server_visibility_cache: Mutex<LruCache<(OwnedServerName, u64), bool>>,
     pub user_visibility_cache: Mutex<LruCache<(OwnedUserId, u64), bool>>,
+    pub joined_count_cache: Mutex<LruCache<u64, u64>>,
 }
 
 impl Service {
@@ -234,6 +238,117 @@ impl Service {
         Ok(currently_member || history_visibility == HistoryVisibility::WorldReadable)
     }
 
+    /// Returns the number of joined members in the room state at this event.
+    ///
+    /// Unlike `state_cache::room_joined_count` this is not the current count. State
+    /// snapshots never change, so the result is cached per shortstatehash.
+    #[tracing::instrument(skip(self))]
+    pub async fn joined_member_count_at_event(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> Result<u64> {
+        let shortstatehash = self.event_shortstatehash(room_id, event_id)?;
+
+        if let Some(count) = self
+            .joined_count_cache
+            .lock()
+            .unwrap()
+            .get_mut(&shortstatehash)
+        {
+            return Ok(*count);
+        }
+
+        let state = self.state_full(shortstatehash).await?;
+        let count = count_joined(
+            state
+                .iter()
+                .filter(|((event_type, _), _)| event_type == &StateEventType::RoomMember)
+                .map(|(_, pdu)| &*pdu.content),
+        )?;
+
+        self.joined_count_cache
+            .lock()
+            .unwrap()
+            .insert(shortstatehash, count);
+
+        Ok(count)
+    }
+
+    /// Returns the state difference between the states at two events of a room.
+    ///
+    /// The result contains the state events only found at `event_a`, the ones only found at
+    /// `event_b` and the (a, b) pairs for state keys that point to different events.
+    #[allow(clippy::type_complexity)]
+    #[tracing::instrument(skip(self))]
+    pub async fn state_diff_between_events(
+        &self,
+        room_id: &RoomId,
+        event_a: &EventId,
+        event_b: &EventId,
+    ) -> Result<(
+        Vec<Arc<PduEvent>>,
+        Vec<Arc<PduEvent>>,
+        Vec<(Arc<PduEvent>, Arc<PduEvent>)>,
+    )> {
+        let state_a = self
+            .state_full_ids(self.event_shortstatehash(room_id, event_a)?)
+            .await?;
+        let state_b = self
+            .state_full_ids(self.event_shortstatehash(room_id, event_b)?)
+            .await?;
+
+        let (only_in_a, only_in_b, changed) = diff_state(&state_a, &state_b);
+
+        let get_pdu = |event_id: &EventId| {
+            services()
+                .rooms
+                .timeline
+                .get_pdu(event_id)?
+                .ok_or_else(|| Error::bad_database("State event in state snapshot not found."))
+        };
+
+        let mut only_in_a = only_in_a
+            .into_iter()
+            .map(&get_pdu)
+            .collect::<Result<Vec<_>>>()?;
+        let mut only_in_b = only_in_b
+            .into_iter()
+            .map(&get_pdu)
+            .collect::<Result<Vec<_>>>()?;
+        let mut changed = changed
+            .into_iter()
+            .map(|(a, b)| Ok((get_pdu(a)?, get_pdu(b)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        only_in_a.sort_by(|a, b| (&a.kind, &a.state_key).cmp(&(&b.kind, &b.state_key)));
+        only_in_b.sort_by(|a, b| (&a.kind, &a.state_key).cmp(&(&b.kind, &b.state_key)));
+        changed.sort_by(|(a, _), (b, _)| (&a.kind, &a.state_key).cmp(&(&b.kind, &b.state_key)));
+
+        Ok((only_in_a, only_in_b, changed))
+    }
+
+    /// Returns the state hash for an event, making sure the event belongs to the room.
+    fn event_shortstatehash(&self, room_id: &RoomId, event_id: &EventId) -> Result<u64> {
+        let pdu = services()
+            .rooms
+            .timeline
+            .get_pdu(event_id)?
+            .ok_or(Error::BadRequest(ErrorKind::NotFound, "Event not found."))?;
+
+        if pdu.room_id != room_id {
+            return Err(Error::BadRequest(
+                ErrorKind::NotFound,
+                "Event not found in this room.",
+            ));
+        }
+
+        self.pdu_shortstatehash(event_id)?.ok_or(Error::BadRequest(
+            ErrorKind::NotFound,
+            "State at event not found.",
+        ))
+    }
+
     /// Returns the state hash for this pdu.
     pub fn pdu_shortstatehash(&self, event_id: &EventId) -> Result<Option<u64>> {
         self.db.pdu_shortstatehash(event_id)
@@ -270,3 +385,123 @@ impl Service {
         self.db.room_state_get(room_id, event_type, state_key)
     }
 }
+
+/// Counts the member event contents whose membership is `join`.
+fn count_joined<'a>(contents: impl Iterator<Item = &'a RawJsonValue>) -> Result<u64> {
+    let mut count = 0;
+    for content in contents {
+        let membership = serde_json::from_str::<RoomMemberEventContent>(content.get())
+            .map_err(|_| Error::bad_database("Invalid room membership event in database."))?
+            .membership;
+
+        if membership == MembershipState::Join {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Diffs two state maps, returning the values only in `a`, the values only in `b` and the
+/// (a, b) value pairs for keys present in both with different values.
+#[allow(clippy::type_complexity)]
+fn diff_state<'a, K: Eq + Hash, V: PartialEq + ?Sized>(
+    a: &'a HashMap<K, Arc<V>>,
+    b: &'a HashMap<K, Arc<V>>,
+) -> (Vec<&'a V>, Vec<&'a V>, Vec<(&'a V, &'a V)>) {
+    let mut only_in_a = Vec::new();
+    let mut changed = Vec::new();
+
+    for (key, value_a) in a {
+        match b.get(key) {
+            None => only_in_a.push(&**value_a),
+            Some(value_b) if value_a != value_b => changed.push((&**value_a, &**value_b)),
+            Some(_) => {}
+        }
+    }
+
+    let only_in_b = b
+        .iter()
+        .filter(|(key, _)| !a.contains_key(key))
+        .map(|(_, value)| &**value)
+        .collect();
+
+    (only_in_a, only_in_b, changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::events::{room::member::MembershipState, RoomEventType};
+    use serde_json::{json, value::to_raw_value};
+
+    use crate::{service::pdu::PduBuilder, services, testing};
+
+    #[tokio::test]
+    async fn count_includes_user_after_join() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let room_id = testing::public_room(&alice).await;
+
+        let join = testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+        let message = testing::message(&room_id, &bob, "hi").await;
+
+        let state_accessor = &services().rooms.state_accessor;
+
+        // The state at an event is the state before it
+        assert_eq!(
+            state_accessor
+                .joined_member_count_at_event(&room_id, &join)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            state_accessor
+                .joined_member_count_at_event(&room_id, &message)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn diff_across_power_level_change() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+
+        let before = testing::message(&room_id, &alice, "before").await;
+        testing::send(
+            &room_id,
+            &alice,
+            PduBuilder {
+                event_type: RoomEventType::RoomPowerLevels,
+                content: to_raw_value(&json!({ "users": { alice.as_str(): 100 }, "ban": 100 }))
+                    .unwrap(),
+                unsigned: None,
+                state_key: Some(String::new()),
+                redacts: None,
+            },
+        )
+        .await
+        .unwrap();
+        let after = testing::message(&room_id, &alice, "after").await;
+
+        let (only_in_before, only_in_after, changed) = services()
+            .rooms
+            .state_accessor
+            .state_diff_between_events(&room_id, &before, &after)
+            .await
+            .unwrap();
+
+        assert!(only_in_before.is_empty());
+        assert!(only_in_after.is_empty());
+        assert_eq!(changed.len(), 1);
+        let (old, new) = &changed[0];
+        assert_eq!(old.kind, RoomEventType::RoomPowerLevels);
+        assert_eq!(new.kind, RoomEventType::RoomPowerLevels);
+        assert_eq!(new.state_key.as_deref(), Some(""));
+        assert_ne!(old.event_id, new.event_id);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{collections::HashSet, sync::Arc};
 
+use super::MembershipOrdering;
 use crate::Result;
 use ruma::{
     events::{AnyStrippedStateEvent, AnySyncStateEvent},
@@ -18,6 +19,20 @@ pub trait Data: Send + Sync {
     ) -> Result<()>;
     fn mark_as_left(&self, user_id: &UserId, room_id: &RoomId) -> Result<()>;
 
+    /// Returns the ordering key of the membership event that was applied last.
+    fn get_membership_ordering(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MembershipOrdering>>;
+
+    fn set_membership_ordering(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+        ordering: MembershipOrdering,
+    ) -> Result<()>;
+
     fn update_joined_count(&self, room_id: &RoomId) -> Result<()>;
 
     fn get_our_real_users(&self, room_id: &RoomId) -> Result<Arc<HashSet<OwnedUserId>>>;
@@ -31,6 +46,10 @@ pub trait Data: Send + Sync {
     /// Makes a user forget a room.
     fn forget(&self, room_id: &RoomId, user_id: &UserId) -> Result<()>;
 
+    /// Removes the member counts of the room and everything left of the given users'
+    /// memberships, including the membership ordering.
+    fn purge_room(&self, room_id: &RoomId, user_ids: &[OwnedUserId]) -> Result<()>;
+
     /// Returns an iterator of all servers participating in this room.
     fn room_servers<'a>(
         &'a self,
@@ -4,26 +4,122 @@ use std::{collections::HashSet, sync::Arc};
 pub use data::Data;
 
 use ruma::{
+    api::client::error::ErrorKind,
     events::{
         direct::DirectEvent,
         ignored_user_list::IgnoredUserListEvent,
-        room::{create::RoomCreateEventContent, member::MembershipState},
+        room::{
+            create::RoomCreateEventContent,
+            member::{MembershipState, RoomMemberEventContent, ThirdPartyInvite},
+        },
         AnyStrippedStateEvent, AnySyncStateEvent, GlobalAccountDataEventType,
-        RoomAccountDataEventType, StateEventType,
+        RoomAccountDataEventType, RoomEventType, StateEventType,
     },
     serde::Raw,
     OwnedRoomId, OwnedServerName, OwnedUserId, RoomId, ServerName, UserId,
 };
 
-use crate::{services, Error, Result};
+use serde_json::value::to_raw_value;
+use tracing::debug;
+
+use crate::{service::pdu::PduBuilder, services, Error, PduEvent, Result};
+
+/// The position of a membership event in the room, used to detect membership events that arrive
+/// out of order over federation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MembershipOrdering {
+    pub depth: u64,
+    pub origin_server_ts: u64,
+}
+
+impl MembershipOrdering {
+    pub fn from_pdu(pdu: &PduEvent) -> Self {
+        Self {
+            depth: pdu.depth.into(),
+            origin_server_ts: pdu.origin_server_ts.into(),
+        }
+    }
+
+    /// Returns false if `self` is strictly older than the last applied membership event.
+    fn replaces(&self, current: Option<MembershipOrdering>) -> bool {
+        current.map_or(true, |current| *self >= current)
+    }
+}
+
+/// The parts of a membership event that only some membership flows set.
+#[derive(Default)]
+pub struct MembershipExtras {
+    pub reason: Option<String>,
+    pub is_direct: Option<bool>,
+    /// Only allowed on invites.
+    pub third_party_invite: Option<ThirdPartyInvite>,
+    /// Only allowed on joins, required for joins through a restricted join rule.
+    pub join_authorized_via_users_server: Option<OwnedUserId>,
+}
 
 pub struct Service {
     pub db: &'static dyn Data,
 }
 
 impl Service {
+    /// Builds the `m.room.member` event for `target` in a room.
+    ///
+    /// Joins, knocks and invites of local users carry their current profile. Remote users get no
+    /// profile, their server fills it in and our cached copy may be stale. Leaves (including
+    /// kicks) and bans keep the content of the current member event and only change the
+    /// membership and reason.
+    #[tracing::instrument(skip(self, extra))]
+    pub fn build_membership_event(
+        &self,
+        room_id: &RoomId,
+        target: &UserId,
+        membership: MembershipState,
+        extra: MembershipExtras,
+    ) -> Result<PduBuilder> {
+        let previous = match membership {
+            MembershipState::Leave | MembershipState::Ban => services()
+                .rooms
+                .state_accessor
+                .room_state_get(room_id, &StateEventType::RoomMember, target.as_str())?
+                .map(|event| {
+                    serde_json::from_str::<RoomMemberEventContent>(event.content.get())
+                        .map_err(|_| Error::bad_database("Invalid member event in database."))
+                })
+                .transpose()?,
+            _ => None,
+        };
+
+        let base = match previous {
+            Some(previous) => previous,
+            None if target.server_name() == services().globals.server_name() => {
+                RoomMemberEventContent {
+                    displayname: services().users.displayname(target)?,
+                    avatar_url: services().users.avatar_url(target)?,
+                    blurhash: services().users.blurhash(target)?,
+                    ..RoomMemberEventContent::new(membership.clone())
+                }
+            }
+            None => RoomMemberEventContent::new(membership.clone()),
+        };
+
+        let content = membership_content(base, membership, extra)?;
+
+        Ok(PduBuilder {
+            event_type: RoomEventType::RoomMember,
+            content: to_raw_value(&content).expect("event is valid, we just created it"),
+            unsigned: None,
+            state_key: Some(target.to_string()),
+            redacts: None,
+        })
+    }
+
     /// Update current membership data.
+    ///
+    /// If `ordering` is given and the membership event it belongs to is older than the one that
+    /// was applied last for this user, the update is ignored. Authoritative updates, like the
+    /// result of state resolution, pass `None` and always apply.
     #[tracing::instrument(skip(self, last_state))]
+    #[allow(clippy::too_many_arguments)]
     pub fn update_membership(
         &self,
         room_id: &RoomId,
@@ -31,8 +127,22 @@ impl Service {
         membership: MembershipState,
         sender: &UserId,
         last_state: Option<Vec<Raw<AnyStrippedStateEvent>>>,
+        ordering: Option<MembershipOrdering>,
         update_joined_count: bool,
     ) -> Result<()> {
+        if let Some(ordering) = ordering {
+            if !ordering.replaces(self.db.get_membership_ordering(room_id, user_id)?) {
+                debug!(
+                    "Ignoring outdated membership {:?} of {}",
+                    membership, user_id
+                );
+                return Ok(());
+            }
+
+            self.db
+                .set_membership_ordering(room_id, user_id, ordering)?;
+        }
+
         // Keep track what remote users exist by adding them as "deactivated" users
         if user_id.server_name() != services().globals.server_name() {
             services().users.create(user_id, None)?;
@@ -175,6 +285,7 @@ impl Service {
             }
             MembershipState::Leave | MembershipState::Ban => {
                 self.db.mark_as_left(user_id, room_id)?;
+                services().rooms.user.forget_room_update(user_id, room_id)?;
             }
             _ => {}
         }
@@ -186,6 +297,12 @@ impl Service {
         Ok(())
     }
 
+    /// Removes what is left of the memberships of a purged room once everyone left it.
+    #[tracing::instrument(skip(self, user_ids))]
+    pub fn purge_room(&self, room_id: &RoomId, user_ids: &[OwnedUserId]) -> Result<()> {
+        self.db.purge_room(room_id, user_ids)
+    }
+
     #[tracing::instrument(skip(self, room_id))]
     pub fn update_joined_count(&self, room_id: &RoomId) -> Result<()> {
         self.db.update_joined_count(room_id)
@@ -346,3 +463,186 @@ impl Service {
         self.db.is_left(user_id, room_id)
     }
 }
+
+/// Applies the membership and the extra fields to `base`, enforcing which fields are allowed for
+/// which membership.
+fn membership_content(
+    mut base: RoomMemberEventContent,
+    membership: MembershipState,
+    extra: MembershipExtras,
+) -> Result<RoomMemberEventContent> {
+    if extra.join_authorized_via_users_server.is_some() && membership != MembershipState::Join {
+        return Err(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "join_authorised_via_users_server is only allowed on joins.",
+        ));
+    }
+
+    if extra.third_party_invite.is_some() && membership != MembershipState::Invite {
+        return Err(Error::BadRequest(
+            ErrorKind::InvalidParam,
+            "third_party_invite is only allowed on invites.",
+        ));
+    }
+
+    base.membership = membership;
+    base.reason = extra.reason;
+    base.third_party_invite = extra.third_party_invite;
+    base.join_authorized_via_users_server = extra.join_authorized_via_users_server;
+    if extra.is_direct.is_some() {
+        base.is_direct = extra.is_direct;
+    }
+
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        events::room::member::{MembershipState, RoomMemberEventContent},
+        user_id,
+    };
+
+    use super::{membership_content, MembershipExtras, MembershipOrdering};
+    use crate::{services, testing};
+
+    #[test]
+    fn older_leave_does_not_replace_newer_join() {
+        let join = MembershipOrdering {
+            depth: 10,
+            origin_server_ts: 2_000,
+        };
+        let stale_leave = MembershipOrdering {
+            depth: 8,
+            origin_server_ts: 1_000,
+        };
+
+        assert!(join.replaces(None));
+        assert!(!stale_leave.replaces(Some(join)));
+        assert!(join.replaces(Some(stale_leave)));
+        assert!(join.replaces(Some(join)));
+    }
+
+    #[tokio::test]
+    async fn stale_membership_update_is_ignored_unless_authoritative() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let room_id = testing::public_room(&alice).await;
+        let join_id = testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+        let join = services()
+            .rooms
+            .timeline
+            .get_pdu(&join_id)
+            .unwrap()
+            .unwrap();
+        let stale = MembershipOrdering {
+            depth: u64::from(join.depth) - 1,
+            origin_server_ts: 0,
+        };
+
+        // A leave from before the join arrives late over federation
+        services()
+            .rooms
+            .state_cache
+            .update_membership(
+                &room_id,
+                &bob,
+                MembershipState::Leave,
+                &bob,
+                None,
+                Some(stale),
+                true,
+            )
+            .unwrap();
+        assert!(services()
+            .rooms
+            .state_cache
+            .is_joined(&bob, &room_id)
+            .unwrap());
+
+        // State resolution picked a ban with a lower depth
+        services()
+            .rooms
+            .state_cache
+            .update_membership(
+                &room_id,
+                &bob,
+                MembershipState::Ban,
+                &alice,
+                None,
+                None,
+                true,
+            )
+            .unwrap();
+        assert!(!services()
+            .rooms
+            .state_cache
+            .is_joined(&bob, &room_id)
+            .unwrap());
+        assert!(services()
+            .rooms
+            .state_cache
+            .is_left(&bob, &room_id)
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn remote_invite_has_no_cached_profile() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let remote = user_id!("@bob:remote.example.org");
+        services().users.create(remote, None).unwrap();
+        services()
+            .users
+            .set_displayname(remote, Some("Outdated Bob".to_owned()))
+            .unwrap();
+
+        let pdu = services()
+            .rooms
+            .state_cache
+            .build_membership_event(
+                &room_id,
+                remote,
+                MembershipState::Invite,
+                MembershipExtras::default(),
+            )
+            .unwrap();
+
+        let content: RoomMemberEventContent = serde_json::from_str(pdu.content.get()).unwrap();
+        assert_eq!(content.membership, MembershipState::Invite);
+        assert_eq!(content.displayname, None);
+    }
+
+    #[test]
+    fn restricted_join_includes_authorising_user() {
+        let content = membership_content(
+            RoomMemberEventContent::new(MembershipState::Join),
+            MembershipState::Join,
+            MembershipExtras {
+                join_authorized_via_users_server: Some(user_id!("@admin:example.org").to_owned()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let json = serde_json::to_value(&content).unwrap();
+        assert_eq!(json["membership"], "join");
+        assert_eq!(
+            json["join_authorised_via_users_server"],
+            "@admin:example.org"
+        );
+
+        // The authorising user makes no sense on any other membership
+        assert!(membership_content(
+            RoomMemberEventContent::new(MembershipState::Join),
+            MembershipState::Leave,
+            MembershipExtras {
+                join_authorized_via_users_server: Some(user_id!("@admin:example.org").to_owned()),
+                ..Default::default()
+            },
+        )
+        .is_err());
+    }
+}
@@ -59,6 +59,9 @@ pub trait Data: Send + Sync {
     /// Removes a pdu and creates a new one with the same id.
     fn replace_pdu(&self, pdu_id: &[u8], pdu: &PduEvent) -> Result<()>;
 
+    /// Removes a pdu from the timeline and the outliers.
+    fn remove_pdu(&self, room_id: &RoomId, event_id: &EventId) -> Result<()>;
+
     /// Returns an iterator over all events and their tokens in a room that happened before the
     /// event with id `until` in reverse-chronological order.
     fn pdus_until<'a>(
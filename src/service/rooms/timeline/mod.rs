@@ -20,7 +20,7 @@ use ruma::{
         push_rules::PushRulesEvent,
         room::{
             create::RoomCreateEventContent, member::MembershipState,
-            power_levels::RoomPowerLevelsEventContent,
+            power_levels::RoomPowerLevelsEventContent, redaction::RoomRedactionEventContent,
         },
         GlobalAccountDataEventType, RoomEventType, StateEventType,
     },
@@ -28,11 +28,11 @@ use ruma::{
     state_res,
     state_res::Event,
     state_res::RoomVersion,
-    uint, CanonicalJsonObject, CanonicalJsonValue, EventId, OwnedEventId, OwnedRoomId,
+    uint, CanonicalJsonObject, CanonicalJsonValue, DeviceId, EventId, OwnedEventId, OwnedRoomId,
     OwnedServerName, RoomAliasId, RoomId, UserId,
 };
 use ruma::{user_id, ServerName};
-use serde::Deserialize;
+use serde::{de::IgnoredAny, Deserialize};
 use serde_json::value::{to_raw_value, RawValue as RawJsonValue};
 use tokio::sync::MutexGuard;
 use tracing::{error, info, warn};
@@ -43,7 +43,7 @@ use crate::{
     services, utils, Error, PduEvent, Result,
 };
 
-use super::state_compressor::CompressedStateEvent;
+use super::{state_cache::MembershipOrdering, state_compressor::CompressedStateEvent};
 
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum PduCount {
@@ -94,7 +94,10 @@ impl Ord for PduCount {
 }
 #[cfg(test)]
 mod tests {
+    use ruma::api::client::sync::sync_events;
+
     use super::*;
+    use crate::{api::client_server::sync_events_route, testing};
 
     #[test]
     fn comparisons() {
@@ -103,6 +106,164 @@ mod tests {
         assert!(PduCount::Normal(1) > PduCount::Backfilled(1));
         assert!(PduCount::Backfilled(1) < PduCount::Normal(1));
     }
+
+    fn topic_event(event_id: &str, topic: &str) -> PduEvent {
+        serde_json::from_value(serde_json::json!({
+            "event_id": event_id,
+            "room_id": "!room:example.org",
+            "sender": "@alice:example.org",
+            "origin_server_ts": 1,
+            "type": "m.room.topic",
+            "content": { "topic": topic },
+            "state_key": "",
+            "prev_events": [],
+            "depth": 1,
+            "auth_events": [],
+            "hashes": { "sha256": "" },
+        }))
+        .unwrap()
+    }
+
+    fn message_event(event_id: &str, sender: &str, origin_server_ts: u64) -> PduEvent {
+        serde_json::from_value(serde_json::json!({
+            "event_id": event_id,
+            "room_id": "!room:example.org",
+            "sender": sender,
+            "origin_server_ts": origin_server_ts,
+            "type": "m.room.message",
+            "content": { "msgtype": "m.text", "body": "hi" },
+            "prev_events": [],
+            "depth": 1,
+            "auth_events": [],
+            "hashes": { "sha256": "" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn only_the_targets_messages_are_redacted() {
+        let spammer = user_id!("@spammer:example.org");
+        let pdus = vec![
+            message_event("$old_spam:example.org", spammer.as_str(), 1),
+            message_event("$bob:example.org", "@bob:example.org", 2),
+            message_event("$spam:example.org", spammer.as_str(), 3),
+        ];
+
+        let redacted = |since| {
+            events_to_redact(pdus.clone().into_iter(), spammer, since)
+                .iter()
+                .map(|event_id| event_id.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            redacted(None),
+            vec!["$old_spam:example.org", "$spam:example.org"]
+        );
+        assert_eq!(redacted(Some(2)), vec!["$spam:example.org"]);
+    }
+
+    fn is_redacted(event_id: &EventId) -> bool {
+        services()
+            .rooms
+            .timeline
+            .get_pdu(event_id)
+            .unwrap()
+            .unwrap()
+            .unsigned
+            .as_ref()
+            .map_or(false, |unsigned| {
+                unsigned.get().contains("\"redacted_because\"")
+            })
+    }
+
+    #[tokio::test]
+    async fn banned_spammer_gets_their_messages_redacted() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let spammer = testing::user("spammer");
+        let room_id = testing::public_room(&alice).await;
+        testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+        testing::membership(&room_id, &spammer, &spammer, MembershipState::Join).await;
+
+        let spam = vec![
+            testing::message(&room_id, &spammer, "spam").await,
+            testing::message(&room_id, &spammer, "more spam").await,
+        ];
+        let ham = vec![
+            testing::message(&room_id, &alice, "hi").await,
+            testing::message(&room_id, &bob, "hello").await,
+        ];
+        testing::membership(&room_id, &alice, &spammer, MembershipState::Ban).await;
+
+        assert_eq!(
+            services()
+                .rooms
+                .timeline
+                .redact_user_events(&room_id, &spammer, &alice, None)
+                .await
+                .unwrap(),
+            2
+        );
+        assert!(spam.iter().all(|event_id| is_redacted(event_id)));
+        assert!(!ham.iter().any(|event_id| is_redacted(event_id)));
+    }
+
+    #[test]
+    fn state_event_gets_prev_content() {
+        let old_topic = topic_event("$old:example.org", "old");
+        let mut unsigned = BTreeMap::new();
+
+        add_prev_content(&mut unsigned, &old_topic).unwrap();
+
+        assert_eq!(
+            unsigned["prev_content"],
+            serde_json::json!({ "topic": "old" })
+        );
+        assert_eq!(unsigned["replaces_state"], "$old:example.org");
+    }
+
+    fn topic(topic: &str) -> PduBuilder {
+        PduBuilder {
+            event_type: RoomEventType::RoomTopic,
+            content: to_raw_value(&serde_json::json!({ "topic": topic })).unwrap(),
+            unsigned: None,
+            state_key: Some(String::new()),
+            redacts: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn synced_state_event_has_prev_content() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        testing::send(&room_id, &alice, topic("old")).await.unwrap();
+        testing::send(&room_id, &alice, topic("new")).await.unwrap();
+
+        let response = match sync_events_route(testing::request(
+            &alice,
+            sync_events::v3::Request::new(),
+        ))
+        .await
+        {
+            Ok(response) => response,
+            Err(_) => panic!("sync failed"),
+        };
+
+        let new_topic = response.rooms.join[&room_id]
+            .timeline
+            .events
+            .iter()
+            .map(|event| serde_json::from_str::<serde_json::Value>(event.json().get()).unwrap())
+            .find(|event| event["content"]["topic"] == "new")
+            .expect("topic is in the timeline");
+        assert_eq!(
+            new_topic["unsigned"]["prev_content"],
+            serde_json::json!({ "topic": "old" })
+        );
+    }
 }
 
 pub struct Service {
@@ -203,6 +364,13 @@ impl Service {
         self.db.replace_pdu(pdu_id, pdu)
     }
 
+    /// Removes a pdu from the timeline. This is only meant to undo events that were never sent
+    /// to other servers, like the events of a room whose creation failed.
+    #[tracing::instrument(skip(self))]
+    pub fn remove_pdu(&self, room_id: &RoomId, event_id: &EventId) -> Result<()> {
+        self.db.remove_pdu(room_id, event_id)
+    }
+
     /// Creates a new persisted data unit and adds it to a room.
     ///
     /// By this point the incoming event should be fully authenticated, no auth happens
@@ -301,16 +469,7 @@ impl Service {
         drop(insert_lock);
 
         // See if the event matches any known pushers
-        let power_levels: RoomPowerLevelsEventContent = services()
-            .rooms
-            .state_accessor
-            .room_state_get(&pdu.room_id, &StateEventType::RoomPowerLevels, "")?
-            .map(|ev| {
-                serde_json::from_str(ev.content.get())
-                    .map_err(|_| Error::bad_database("invalid m.room.power_levels event"))
-            })
-            .transpose()?
-            .unwrap_or_default();
+        let power_levels = services().pusher.room_power_levels(&pdu.room_id)?;
 
         let sync_pdu = pdu.to_sync_room_event();
 
@@ -416,8 +575,20 @@ impl Service {
                         content.membership,
                         &pdu.sender,
                         invite_state,
+                        Some(MembershipOrdering::from_pdu(pdu)),
                         true,
                     )?;
+
+                    // Invited users are not joined, but the room still changed for them
+                    if content.membership == MembershipState::Invite
+                        && target_user_id.server_name() == services().globals.server_name()
+                    {
+                        services().rooms.user.mark_room_updated(
+                            &target_user_id,
+                            &pdu.room_id,
+                            count2,
+                        )?;
+                    }
                 }
             }
             RoomEventType::RoomMessage => {
@@ -458,6 +629,12 @@ impl Service {
             _ => {}
         }
 
+        // After the membership update above, so users that just joined are included
+        services()
+            .rooms
+            .user
+            .mark_room_updated_for_local_users(&pdu.room_id, count2)?;
+
         for appservice in services().appservice.all()? {
             if services()
                 .rooms
@@ -969,6 +1146,155 @@ impl Service {
         Ok(())
     }
 
+    /// Fills in the parts of `unsigned` that depend on who is looking at the event and when.
+    ///
+    /// - `age` is relative to now, so this has to run right before the event is sent out
+    /// - `transaction_id` is only kept for the device that sent the event
+    /// - `redacted_because` is stored on the pdu when it is redacted and kept as is
+    /// - State events get `prev_content` and `replaces_state` from the state before them
+    ///
+    /// Relations are not tracked by this server, so no bundles are added.
+    #[tracing::instrument(skip(self, pdu))]
+    pub fn enrich_unsigned(
+        &self,
+        pdu: &mut PduEvent,
+        for_user: &UserId,
+        for_device: Option<&DeviceId>,
+    ) -> Result<()> {
+        let mut unsigned: BTreeMap<String, serde_json::Value> = pdu
+            .unsigned
+            .as_ref()
+            .map(|unsigned| serde_json::from_str(unsigned.get()))
+            .transpose()
+            .map_err(|_| Error::bad_database("Invalid unsigned in pdu event"))?
+            .unwrap_or_default();
+
+        if let Some(txn_id) = unsigned
+            .get("transaction_id")
+            .and_then(|txn_id| txn_id.as_str())
+        {
+            let own_device = pdu.sender == for_user
+                && services()
+                    .transaction_ids
+                    .existing_txnid(for_user, for_device, txn_id.into())?
+                    .map_or(false, |event_id| event_id == pdu.event_id.as_bytes());
+
+            if !own_device {
+                unsigned.remove("transaction_id");
+            }
+        }
+
+        if let Some(state_key) = &pdu.state_key {
+            if let Some(prev_pdu) = services()
+                .rooms
+                .state_accessor
+                .pdu_shortstatehash(&pdu.event_id)?
+                .map(|shortstatehash| {
+                    services().rooms.state_accessor.state_get(
+                        shortstatehash,
+                        &pdu.kind.to_string().into(),
+                        state_key,
+                    )
+                })
+                .transpose()?
+                .flatten()
+            {
+                add_prev_content(&mut unsigned, &prev_pdu)?;
+            }
+        }
+
+        unsigned.insert(
+            "age".to_owned(),
+            utils::millis_since_unix_epoch()
+                .saturating_sub(pdu.origin_server_ts.into())
+                .into(),
+        );
+
+        pdu.unsigned = Some(to_raw_value(&unsigned).expect("unsigned is valid"));
+
+        Ok(())
+    }
+
+    /// Redacts the events `target_user` sent in the room, optionally only those sent at or after
+    /// `since` (in milliseconds since the unix epoch). Returns how many events were redacted.
+    ///
+    /// Events whose redaction fails are logged and skipped, so the others are still redacted.
+    #[tracing::instrument(skip(self))]
+    pub async fn redact_user_events(
+        &self,
+        room_id: &RoomId,
+        target_user: &UserId,
+        redactor: &UserId,
+        since: Option<u64>,
+    ) -> Result<usize> {
+        let power_levels: RoomPowerLevelsEventContent = services()
+            .rooms
+            .state_accessor
+            .room_state_get(room_id, &StateEventType::RoomPowerLevels, "")?
+            .map(|ev| {
+                serde_json::from_str(ev.content.get())
+                    .map_err(|_| Error::bad_database("invalid m.room.power_levels event"))
+            })
+            .transpose()?
+            .unwrap_or_default();
+        let redactor_level = power_levels
+            .users
+            .get(redactor)
+            .copied()
+            .unwrap_or(power_levels.users_default);
+
+        if redactor != target_user && redactor_level < power_levels.redact {
+            return Err(Error::BadRequest(
+                ErrorKind::Forbidden,
+                "You don't have permission to redact other users' events.",
+            ));
+        }
+
+        let event_ids = events_to_redact(
+            self.all_pdus(redactor, room_id)?
+                .filter_map(|r| r.ok())
+                .map(|(_, pdu)| pdu),
+            target_user,
+            since,
+        );
+
+        let mutex_state = Arc::clone(
+            services()
+                .globals
+                .roomid_mutex_state
+                .write()
+                .unwrap()
+                .entry(room_id.to_owned())
+                .or_default(),
+        );
+        let state_lock = mutex_state.lock().await;
+
+        let mut redacted = 0;
+        for event_id in &event_ids {
+            if let Err(e) = self.build_and_append_pdu(
+                PduBuilder {
+                    event_type: RoomEventType::RoomRedaction,
+                    content: to_raw_value(&RoomRedactionEventContent { reason: None })
+                        .expect("event is valid, we just created it"),
+                    unsigned: None,
+                    state_key: None,
+                    redacts: Some(Arc::clone(event_id)),
+                },
+                redactor,
+                room_id,
+                &state_lock,
+            ) {
+                warn!("Failed to redact {}, skipping: {}", event_id, e);
+                continue;
+            }
+            redacted += 1;
+        }
+
+        drop(state_lock);
+
+        Ok(redacted)
+    }
+
     #[tracing::instrument(skip(self, room_id))]
     pub async fn backfill_if_required(&self, room_id: &RoomId, from: PduCount) -> Result<()> {
         let first_pdu = self
@@ -1124,3 +1450,50 @@ impl Service {
         Ok(())
     }
 }
+
+/// Picks the events of `target_user` sent at or after `since` that are not redacted yet. State
+/// events are left alone, redacting them would change the room state.
+fn events_to_redact(
+    pdus: impl Iterator<Item = PduEvent>,
+    target_user: &UserId,
+    since: Option<u64>,
+) -> Vec<Arc<EventId>> {
+    pdus.filter(|pdu| {
+        pdu.sender == target_user
+            && pdu.state_key.is_none()
+            && pdu.kind != RoomEventType::RoomRedaction
+            && since.map_or(true, |since| u64::from(pdu.origin_server_ts) >= since)
+            && !pdu
+                .unsigned
+                .as_ref()
+                .and_then(|unsigned| {
+                    serde_json::from_str::<BTreeMap<String, IgnoredAny>>(unsigned.get()).ok()
+                })
+                .map_or(false, |unsigned| unsigned.contains_key("redacted_because"))
+    })
+    .map(|pdu| pdu.event_id)
+    .collect()
+}
+
+/// Adds `prev_content` and `replaces_state` for the state event `prev_pdu` was replaced by,
+/// unless the event already carries them.
+fn add_prev_content(
+    unsigned: &mut BTreeMap<String, serde_json::Value>,
+    prev_pdu: &PduEvent,
+) -> Result<()> {
+    if unsigned.contains_key("prev_content") {
+        return Ok(());
+    }
+
+    unsigned.insert(
+        "prev_content".to_owned(),
+        serde_json::from_str(prev_pdu.content.get())
+            .map_err(|_| Error::bad_database("Invalid content in pdu event"))?,
+    );
+    unsigned.insert(
+        "replaces_state".to_owned(),
+        prev_pdu.event_id.as_str().into(),
+    );
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use crate::Result;
-use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use ruma::{DeviceId, OwnedRoomId, OwnedUserId, RoomId, UserId};
 
 pub trait Data: Send + Sync {
     fn reset_notification_counts(&self, user_id: &UserId, room_id: &RoomId) -> Result<()>;
@@ -11,6 +11,43 @@ pub trait Data: Send + Sync {
     // Returns the count at which the last reset_notification_counts was called
     fn last_notification_read(&self, user_id: &UserId, room_id: &RoomId) -> Result<u64>;
 
+    /// Records that the room changed for the user at `count`.
+    fn set_room_last_update(&self, user_id: &UserId, room_id: &RoomId, count: u64) -> Result<()>;
+
+    fn remove_room_last_update(&self, user_id: &UserId, room_id: &RoomId) -> Result<()>;
+
+    /// Removes the notification counts, last update and initial sync omissions of the room for the
+    /// given users.
+    fn purge_room(&self, room_id: &RoomId, user_ids: &[OwnedUserId]) -> Result<()>;
+
+    /// Returns all rooms of the user together with the count of their last change.
+    fn rooms_last_update<'a>(
+        &'a self,
+        user_id: &UserId,
+    ) -> Box<dyn Iterator<Item = Result<(OwnedRoomId, u64)>> + 'a>;
+
+    /// Replaces the rooms that were left out of the last initial sync of the device.
+    fn set_initial_sync_omitted(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_ids: &[OwnedRoomId],
+    ) -> Result<()>;
+
+    fn was_omitted_from_initial_sync(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_id: &RoomId,
+    ) -> Result<bool>;
+
+    fn remove_initial_sync_omitted(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_id: &RoomId,
+    ) -> Result<()>;
+
     fn associate_token_shortstatehash(
         &self,
         room_id: &RoomId,
@@ -1,9 +1,11 @@
 mod data;
 
+use std::{cmp::Reverse, collections::HashMap};
+
 pub use data::Data;
-use ruma::{OwnedRoomId, OwnedUserId, RoomId, UserId};
+use ruma::{DeviceId, OwnedRoomId, OwnedUserId, RoomId, UserId};
 
-use crate::Result;
+use crate::{services, Result};
 
 pub struct Service {
     pub db: &'static dyn Data,
@@ -26,6 +28,93 @@ impl Service {
         self.db.last_notification_read(user_id, room_id)
     }
 
+    /// Marks the room as changed for the user, so it shows up in `rooms_changed_since`.
+    pub fn mark_room_updated(&self, user_id: &UserId, room_id: &RoomId, count: u64) -> Result<()> {
+        self.db.set_room_last_update(user_id, room_id, count)
+    }
+
+    /// Stops tracking changes of the room for the user, e.g. because they left it.
+    pub fn forget_room_update(&self, user_id: &UserId, room_id: &RoomId) -> Result<()> {
+        self.db.remove_room_last_update(user_id, room_id)
+    }
+
+    /// Removes the notification counts and change tracking of a purged room.
+    pub fn purge_room(&self, room_id: &RoomId, user_ids: &[OwnedUserId]) -> Result<()> {
+        self.db.purge_room(room_id, user_ids)
+    }
+
+    /// Marks the room as changed for all local users that are joined to it.
+    pub fn mark_room_updated_for_local_users(&self, room_id: &RoomId, count: u64) -> Result<()> {
+        for user_id in services()
+            .rooms
+            .state_cache
+            .get_our_real_users(room_id)?
+            .iter()
+        {
+            self.mark_room_updated(user_id, room_id, count)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the joined and invited rooms of the user that received events, receipts or room
+    /// account data after `since`.
+    #[tracing::instrument(skip(self))]
+    pub fn rooms_changed_since(&self, user_id: &UserId, since: u64) -> Result<Vec<OwnedRoomId>> {
+        changed_since(self.db.rooms_last_update(user_id), since)
+    }
+
+    /// Sorts `room_ids` so the rooms that changed most recently for the user come first. Rooms
+    /// without any tracked change come last.
+    #[tracing::instrument(skip(self, room_ids))]
+    pub fn sort_by_last_activity(
+        &self,
+        user_id: &UserId,
+        mut room_ids: Vec<OwnedRoomId>,
+    ) -> Result<Vec<OwnedRoomId>> {
+        let last_updates = self
+            .db
+            .rooms_last_update(user_id)
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        room_ids.sort_by_key(|room_id| Reverse(last_updates.get(room_id).copied()));
+
+        Ok(room_ids)
+    }
+
+    /// Remembers the rooms that were left out of an initial sync of the device, replacing the
+    /// ones of its previous initial sync.
+    pub fn set_initial_sync_omitted(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_ids: &[OwnedRoomId],
+    ) -> Result<()> {
+        self.db
+            .set_initial_sync_omitted(user_id, device_id, room_ids)
+    }
+
+    pub fn was_omitted_from_initial_sync(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_id: &RoomId,
+    ) -> Result<bool> {
+        self.db
+            .was_omitted_from_initial_sync(user_id, device_id, room_id)
+    }
+
+    /// Marks that the device received the room after all.
+    pub fn remove_initial_sync_omitted(
+        &self,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        room_id: &RoomId,
+    ) -> Result<()> {
+        self.db
+            .remove_initial_sync_omitted(user_id, device_id, room_id)
+    }
+
     pub fn associate_token_shortstatehash(
         &self,
         room_id: &RoomId,
@@ -47,3 +136,214 @@ impl Service {
         self.db.get_shared_rooms(users)
     }
 }
+
+fn changed_since(
+    last_updates: impl Iterator<Item = Result<(OwnedRoomId, u64)>>,
+    since: u64,
+) -> Result<Vec<OwnedRoomId>> {
+    let mut rooms = Vec::new();
+    for r in last_updates {
+        let (room_id, count) = r?;
+        if count > since {
+            rooms.push(room_id);
+        }
+    }
+
+    Ok(rooms)
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{api::client::sync::sync_events, events::room::member::MembershipState, room_id};
+
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{
+        api::client_server::sync_events_route, service::rooms::timeline::PduCount, testing, utils,
+    };
+
+    #[test]
+    fn unchanged_rooms_are_excluded() {
+        let last_updates = vec![
+            Ok((room_id!("!quiet:example.com").to_owned(), 5)),
+            Ok((room_id!("!busy:example.com").to_owned(), 12)),
+        ];
+
+        assert_eq!(
+            changed_since(last_updates.into_iter(), 10).unwrap(),
+            vec![room_id!("!busy:example.com").to_owned()]
+        );
+    }
+
+    #[tokio::test]
+    async fn joining_marks_the_room_for_the_joiner() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let room_id = testing::public_room(&alice).await;
+        let since = services().globals.current_count().unwrap();
+
+        testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+
+        assert!(services()
+            .rooms
+            .user
+            .rooms_changed_since(&bob, since)
+            .unwrap()
+            .contains(&room_id));
+    }
+
+    #[tokio::test]
+    async fn private_read_marker_marks_the_room_at_the_current_count() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let event_id = testing::message(&room_id, &alice, "hello").await;
+        let count = match services()
+            .rooms
+            .timeline
+            .get_pdu_count(&event_id)
+            .unwrap()
+            .unwrap()
+        {
+            PduCount::Normal(count) => count,
+            PduCount::Backfilled(_) => unreachable!("the event was just sent"),
+        };
+        testing::message(&room_id, &alice, "later").await;
+        let since = services().globals.current_count().unwrap();
+
+        // The marker points at an older event, but setting it is a new change
+        services()
+            .rooms
+            .edus
+            .read_receipt
+            .private_read_set(&room_id, &alice, count)
+            .unwrap();
+
+        assert!(services()
+            .rooms
+            .user
+            .rooms_changed_since(&alice, since)
+            .unwrap()
+            .contains(&room_id));
+    }
+
+    #[tokio::test]
+    async fn leaving_stops_tracking_the_room() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let bob = testing::user("bob");
+        let room_id = testing::public_room(&alice).await;
+        testing::membership(&room_id, &bob, &bob, MembershipState::Join).await;
+
+        testing::membership(&room_id, &bob, &bob, MembershipState::Leave).await;
+        testing::message(&room_id, &alice, "after bob left").await;
+
+        assert!(!services()
+            .rooms
+            .user
+            .rooms_changed_since(&bob, 0)
+            .unwrap()
+            .contains(&room_id));
+    }
+
+    async fn sync(user: &UserId, since: Option<String>) -> sync_events::v3::Response {
+        let mut body = sync_events::v3::Request::new();
+        body.since = since;
+
+        match sync_events_route(testing::request(user, body)).await {
+            Ok(response) => response,
+            Err(_) => panic!("sync failed"),
+        }
+    }
+
+    fn has_create_event(response: &sync_events::v3::Response, room_id: &RoomId) -> bool {
+        response.rooms.join[room_id]
+            .state
+            .events
+            .iter()
+            .any(|event| event.json().get().contains("\"m.room.create\""))
+    }
+
+    /// Returns the content of the partial initial sync marker of the response, if any.
+    fn partial_initial_sync(response: &sync_events::v3::Response) -> Option<serde_json::Value> {
+        response.account_data.events.iter().find_map(|event| {
+            let mut event: serde_json::Value = serde_json::from_str(event.json().get()).unwrap();
+            (event["type"] == "rs.conduit.partial_initial_sync").then(|| event["content"].take())
+        })
+    }
+
+    #[tokio::test]
+    async fn rooms_left_out_of_the_initial_sync_later_get_their_full_state() {
+        let _db = testing::database().await;
+        let max_rooms = services().globals.max_initial_sync_rooms().unwrap() as usize;
+        let alice = testing::user("alice");
+        let mut room_ids = Vec::new();
+        for _ in 0..max_rooms + 2 {
+            room_ids.push(testing::public_room(&alice).await);
+        }
+        // The rooms created first become the most recently active ones
+        for room_id in &room_ids[..max_rooms] {
+            testing::message(room_id, &alice, "hello").await;
+        }
+
+        let initial = sync(&alice, None).await;
+        assert_eq!(
+            initial.rooms.join.keys().collect::<HashSet<_>>(),
+            room_ids[..max_rooms].iter().collect::<HashSet<_>>()
+        );
+        // Clients are told that rooms were left out
+        assert_eq!(
+            partial_initial_sync(&initial),
+            Some(serde_json::json!({ "omitted_rooms": 2 }))
+        );
+
+        // Nothing happened in the omitted rooms yet
+        let quiet = sync(&alice, Some(initial.next_batch)).await;
+        assert!(quiet.rooms.join.is_empty());
+        assert_eq!(partial_initial_sync(&quiet), None);
+
+        let omitted = &room_ids[max_rooms];
+        testing::message(omitted, &alice, "activity").await;
+        let incremental = sync(&alice, Some(quiet.next_batch)).await;
+        assert!(has_create_event(&incremental, omitted));
+        assert!(!incremental
+            .rooms
+            .join
+            .contains_key(&room_ids[max_rooms + 1]));
+
+        testing::message(omitted, &alice, "more activity").await;
+        let later = sync(&alice, Some(incremental.next_batch)).await;
+        assert!(!has_create_event(&later, omitted));
+    }
+
+    #[tokio::test]
+    async fn incremental_sync_skips_unchanged_rooms_without_losing_them() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let busy = testing::public_room(&alice).await;
+        let quiet = testing::public_room(&alice).await;
+        let initial = sync(&alice, None).await;
+
+        testing::message(&busy, &alice, "hello").await;
+        let first = sync(&alice, Some(initial.next_batch)).await;
+        assert!(first.rooms.join.contains_key(&busy));
+        assert!(!first.rooms.join.contains_key(&quiet));
+
+        // Typing doesn't mark the room, but still has to be synced
+        services()
+            .rooms
+            .edus
+            .typing
+            .typing_add(&alice, &quiet, utils::millis_since_unix_epoch() + 10_000)
+            .unwrap();
+        let second = sync(&alice, Some(first.next_batch)).await;
+        assert!(!second.rooms.join[&quiet].ephemeral.events.is_empty());
+        assert!(!has_create_event(&second, &quiet));
+
+        testing::message(&quiet, &alice, "finally").await;
+        let third = sync(&alice, Some(second.next_batch)).await;
+        assert!(!has_create_event(&third, &quiet));
+    }
+}
@@ -5,6 +5,7 @@ pub use data::Data;
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
     fmt::Debug,
+    future::Future,
     sync::Arc,
     time::{Duration, Instant},
 };
@@ -88,6 +89,14 @@ pub struct Service {
 
     /// The state for a given state hash.
     pub(super) maximum_requests: Arc<Semaphore>,
+    /// Limits how many transactions are sent at the same time, if configured. There is at most
+    /// one transaction per destination in flight, so this is the number of destinations served in
+    /// parallel, and unreachable destinations hold their worker until they time out.
+    ///
+    /// Unlike `maximum_requests`, which is shared with every other outgoing request of the server
+    /// and only held while a request is in flight, a worker is held for the whole transaction,
+    /// including building and signing it.
+    workers: Option<Arc<Semaphore>>,
     pub sender: mpsc::UnboundedSender<(OutgoingKind, SendingEventType, Vec<u8>)>,
     receiver: Mutex<mpsc::UnboundedReceiver<(OutgoingKind, SendingEventType, Vec<u8>)>>,
 }
@@ -106,6 +115,9 @@ impl Service {
             sender,
             receiver: Mutex::new(receiver),
             maximum_requests: Arc::new(Semaphore::new(config.max_concurrent_requests as usize)),
+            workers: config
+                .sender_workers
+                .map(|workers| Arc::new(Semaphore::new(workers.max(1) as usize))),
         })
     }
 
@@ -145,7 +157,7 @@ impl Service {
 
         for (outgoing_kind, events) in initial_transactions {
             current_transaction_status.insert(outgoing_kind.clone(), TransactionStatus::Running);
-            futures.push(Self::handle_events(outgoing_kind.clone(), events));
+            futures.push(self.run_transaction(outgoing_kind.clone(), events));
         }
 
         loop {
@@ -163,7 +175,7 @@ impl Service {
                                 self.db.mark_as_active(&new_events)?;
 
                                 futures.push(
-                                    Self::handle_events(
+                                    self.run_transaction(
                                         outgoing_kind.clone(),
                                         new_events.into_iter().map(|(event, _)| event).collect(),
                                     )
@@ -190,13 +202,34 @@ impl Service {
                         vec![(event, key)],
                         &mut current_transaction_status,
                     ) {
-                        futures.push(Self::handle_events(outgoing_kind, events));
+                        futures.push(self.run_transaction(outgoing_kind, events));
                     }
                 }
             }
         }
     }
 
+    /// Sends a transaction on its own task, waiting for a free sender worker if their number is
+    /// limited.
+    fn run_transaction(
+        &self,
+        outgoing_kind: OutgoingKind,
+        events: Vec<SendingEventType>,
+    ) -> impl Future<Output = Result<OutgoingKind, (OutgoingKind, Error)>> {
+        let workers = self.workers.clone();
+
+        async move {
+            run_on_worker(workers, Self::handle_events(outgoing_kind.clone(), events))
+                .await
+                .unwrap_or_else(|| {
+                    Err((
+                        outgoing_kind,
+                        Error::BadServerResponse("Sending worker panicked."),
+                    ))
+                })
+        }
+    }
+
     #[tracing::instrument(skip(self, outgoing_kind, new_events, current_transaction_status))]
     fn select_events(
         &self,
@@ -716,3 +749,155 @@ impl Service {
         response
     }
 }
+
+/// Runs `transaction` on its own task, as soon as one of the `workers` is free if there is a
+/// limit. Returns `None` if the task panicked.
+async fn run_on_worker<T: Send + 'static>(
+    workers: Option<Arc<Semaphore>>,
+    transaction: impl Future<Output = T> + Send + 'static,
+) -> Option<T> {
+    tokio::spawn(async move {
+        let _permit = match workers {
+            Some(workers) => Some(
+                workers
+                    .acquire_owned()
+                    .await
+                    .expect("worker semaphore is never closed"),
+            ),
+            None => None,
+        };
+        transaction.await
+    })
+    .await
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use tokio::{
+        net::TcpListener,
+        sync::{mpsc, oneshot, Semaphore},
+    };
+
+    use super::run_on_worker;
+    use crate::{services, testing, utils};
+
+    /// Registers an appservice listening at `addr` and returns its id.
+    fn register_appservice(addr: SocketAddr) -> String {
+        let id = format!("sending_{}", utils::random_string(8).to_lowercase());
+        let registration = serde_yaml::from_str(&format!(
+            "
+            id: {id}
+            url: http://{addr}
+            as_token: {id}
+            hs_token: {id}
+            sender_localpart: {id}
+            namespaces:
+              users: []
+              aliases: []
+              rooms: []
+            "
+        ))
+        .expect("registration is valid");
+
+        services()
+            .appservice
+            .register_appservice(registration)
+            .expect("appservice can be registered")
+    }
+
+    #[tokio::test]
+    async fn unreachable_destinations_do_not_hold_back_others() {
+        let _db = testing::database().await;
+        let alice = testing::user("alice");
+        let room_id = testing::public_room(&alice).await;
+        let event_id = testing::message(&room_id, &alice, "hello").await;
+        let pdu_id = services()
+            .rooms
+            .timeline
+            .get_pdu_id(&event_id)
+            .unwrap()
+            .expect("message was stored");
+
+        // These accept connections but never answer, like a server that hangs
+        let mut unreachable = Vec::new();
+        for _ in 0..20 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let id = register_appservice(listener.local_addr().unwrap());
+            services()
+                .sending
+                .send_pdu_appservice(id.clone(), pdu_id.clone())
+                .unwrap();
+            unreachable.push((id, listener));
+        }
+
+        let reachable = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let id = register_appservice(reachable.local_addr().unwrap());
+        services()
+            .sending
+            .send_pdu_appservice(id.clone(), pdu_id)
+            .unwrap();
+
+        let reached = tokio::time::timeout(Duration::from_secs(10), reachable.accept()).await;
+
+        for id in unreachable.iter().map(|(id, _)| id).chain([&id]) {
+            services().appservice.unregister_appservice(id).unwrap();
+        }
+
+        assert!(reached.is_ok());
+    }
+
+    #[tokio::test]
+    async fn worker_limit_is_shared_between_destinations() {
+        let workers = Some(Arc::new(Semaphore::new(2)));
+
+        // Each transaction reports when it starts and then waits to be finished
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+        let mut finish = Vec::new();
+        let mut transactions = Vec::new();
+        for i in 0..3 {
+            let started_tx = started_tx.clone();
+            let (finish_tx, finish_rx) = oneshot::channel::<()>();
+            finish.push(Some(finish_tx));
+            transactions.push(tokio::spawn(run_on_worker(workers.clone(), async move {
+                started_tx.send(i).unwrap();
+                finish_rx.await.unwrap();
+            })));
+        }
+
+        // Two destinations are served concurrently...
+        let timeout = Duration::from_secs(10);
+        let first = tokio::time::timeout(timeout, started_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let second = tokio::time::timeout(timeout, started_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(first, second);
+
+        // ...while the third one waits for a free worker
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), started_rx.recv())
+                .await
+                .is_err()
+        );
+
+        finish[first].take().unwrap().send(()).unwrap();
+        let third = tokio::time::timeout(timeout, started_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(third != first && third != second);
+
+        for finish_tx in finish.into_iter().flatten() {
+            finish_tx.send(()).unwrap();
+        }
+        for transaction in transactions {
+            assert_eq!(transaction.await.unwrap(), Some(()));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Setup for tests that need a database and the global services.
+
+use std::sync::{Arc, Once};
+
+use lazy_static::lazy_static;
+use ruma::{
+    api::client::room::create_room::{self, v3::RoomPreset},
+    device_id,
+    events::{
+        room::{member::MembershipState, message::RoomMessageEventContent},
+        RoomEventType,
+    },
+    EventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
+};
+use serde_json::{json, value::to_raw_value};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::{
+    api::client_server::create_room_route,
+    service::{pdu::PduBuilder, rooms::state_cache::MembershipExtras},
+    services, utils, Config, KeyValueDatabase, Result, Ruma,
+};
+
+lazy_static! {
+    static ref DATABASE: Mutex<()> = Mutex::new(());
+}
+
+static LOAD: Once = Once::new();
+
+/// Loads the services on a fresh database the first time it is called.
+///
+/// The services are global, so the returned guard has to be held for the whole test to keep
+/// tests from seeing each other's changes to settings like maintenance mode.
+pub async fn database() -> MutexGuard<'static, ()> {
+    LOAD.call_once(|| {
+        let path = std::env::temp_dir().join(format!("conduit-tests-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        let config: Config = serde_json::from_value(json!({
+            "server_name": "localhost",
+            "database_backend": "sqlite",
+            "database_path": path,
+            "max_initial_sync_rooms": 3,
+            "max_aliases_per_room": 3,
+            "presence_enabled": false,
+        }))
+        .expect("test config is valid");
+
+        // The services spawn tasks that have to outlive the runtime of a single test
+        std::thread::spawn(move || {
+            let runtime = Box::leak(Box::new(
+                tokio::runtime::Runtime::new().expect("runtime can be created"),
+            ));
+            runtime
+                .block_on(KeyValueDatabase::load_or_create(config))
+                .expect("database can be created");
+        })
+        .join()
+        .expect("database setup does not panic");
+    });
+
+    DATABASE.lock().await
+}
+
+/// Creates a local user with a unique id.
+pub fn user(localpart: &str) -> OwnedUserId {
+    let user_id = UserId::parse_with_server_name(
+        format!("{}_{}", localpart, utils::random_string(8).to_lowercase()),
+        services().globals.server_name(),
+    )
+    .expect("localpart is valid");
+
+    services()
+        .users
+        .create(&user_id, Some("password"))
+        .expect("user can be created");
+
+    user_id
+}
+
+/// Wraps `body` like a request `user` sent from the device `TESTDEVICE`.
+pub fn request<T>(user: &UserId, body: T) -> Ruma<T> {
+    Ruma {
+        body,
+        sender_user: Some(user.to_owned()),
+        sender_device: Some(device_id!("TESTDEVICE").to_owned()),
+        sender_servername: None,
+        json_body: None,
+        from_appservice: false,
+    }
+}
+
+/// Creates a public room through the client API.
+pub async fn public_room(creator: &UserId) -> OwnedRoomId {
+    let mut body = create_room::v3::Request::new();
+    body.preset = Some(RoomPreset::PublicChat);
+
+    create_room_route(request(creator, body))
+        .await
+        .expect("room can be created")
+        .room_id
+}
+
+/// Builds and appends an event while holding the room's state lock.
+pub async fn send(room_id: &RoomId, sender: &UserId, pdu: PduBuilder) -> Result<Arc<EventId>> {
+    let mutex_state = Arc::clone(
+        services()
+            .globals
+            .roomid_mutex_state
+            .write()
+            .unwrap()
+            .entry(room_id.to_owned())
+            .or_default(),
+    );
+    let state_lock = mutex_state.lock().await;
+
+    services()
+        .rooms
+        .timeline
+        .build_and_append_pdu(pdu, sender, room_id, &state_lock)
+}
+
+/// Sends a plain text message.
+pub async fn message(room_id: &RoomId, sender: &UserId, body: &str) -> Arc<EventId> {
+    send(
+        room_id,
+        sender,
+        PduBuilder {
+            event_type: RoomEventType::RoomMessage,
+            content: to_raw_value(&RoomMessageEventContent::text_plain(body))
+                .expect("event is valid"),
+            unsigned: None,
+            state_key: None,
+            redacts: None,
+        },
+    )
+    .await
+    .expect("message can be sent")
+}
+
+/// Changes the membership of `target` with an event sent by `sender`.
+pub async fn membership(
+    room_id: &RoomId,
+    sender: &UserId,
+    target: &UserId,
+    membership: MembershipState,
+) -> Arc<EventId> {
+    let pdu = services()
+        .rooms
+        .state_cache
+        .build_membership_event(room_id, target, membership, MembershipExtras::default())
+        .expect("membership event can be built");
+
+    send(room_id, sender, pdu)
+        .await
+        .expect("membership event can be sent")
+}